@@ -0,0 +1,93 @@
+use amethyst_imgui::{imgui, imgui::im_str, ImguiContextId, ImguiDrawData, ImguiState, ImguiVertex};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use imgui_winit_support::WinitPlatform;
+
+/// Each filled rectangle is 4 vertices, so the frame has 100k of them.
+const RECTS: usize = 25_000;
+
+/// The vertex format uploaded before vertices were written in imgui's layout, kept here as the baseline.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ImguiArgs {
+	position: [f32; 2],
+	tex_coord: [f32; 2],
+	color: [f32; 4],
+}
+
+impl From<imgui::DrawVert> for ImguiArgs {
+	fn from(other: imgui::DrawVert) -> Self {
+		Self {
+			position: other.pos,
+			tex_coord: other.uv,
+			color: [
+				f32::from(other.col[0]) / 255.0,
+				f32::from(other.col[1]) / 255.0,
+				f32::from(other.col[2]) / 255.0,
+				f32::from(other.col[3]) / 255.0,
+			],
+		}
+	}
+}
+
+/// Draw data of a frame filled with small rectangles, built without a window like the `empty` backend does.
+fn stress_draw_data() -> ImguiDrawData {
+	let mut context = imgui::Context::create();
+	context.set_ini_filename(None);
+	context.io_mut().display_size = [800.0, 600.0];
+	let platform = WinitPlatform::init(&mut context);
+	let mut state = ImguiState::new(ImguiContextId::DEFAULT, context, platform, Default::default()).unwrap();
+
+	let ui = state.begin_frame().unwrap();
+	imgui::Window::new(im_str!("Stress test"))
+		.position([0.0, 0.0], imgui::Condition::Always)
+		.size([800.0, 600.0], imgui::Condition::Always)
+		.build(ui, || {
+			let origin = ui.cursor_screen_pos();
+			let columns = (ui.content_region_avail()[0].max(4.0) / 4.0) as usize;
+			let draw_list = ui.get_window_draw_list();
+			for i in 0..RECTS {
+				let x = origin[0] + (i % columns) as f32 * 4.0;
+				let y = origin[1] + (i / columns) as f32 * 4.0;
+				let color = [(i % 255) as f32 / 255.0, 0.5, 1.0 - (i % 255) as f32 / 255.0, 1.0];
+				draw_list.add_rect([x, y], [x + 3.0, y + 3.0], color).filled(true).build();
+			}
+		});
+	state.end_frame().unwrap().clone()
+}
+
+/// Compares writing draw lists to a vertex buffer, stood in for by a reused `Vec`, as `DrawImgui::prepare` does now
+/// with converting every vertex as it did before.
+fn vertex_upload(c: &mut Criterion) {
+	let draw_data = stress_draw_data();
+	let vertex_count: usize = draw_data.draw_lists.iter().map(|list| list.vertices.len()).sum();
+
+	let mut group = c.benchmark_group("vertex_upload");
+	group.throughput(Throughput::Elements(vertex_count as u64));
+
+	let mut buffer = Vec::<ImguiVertex>::with_capacity(vertex_count);
+	group.bench_function("imgui_layout", |b| {
+		b.iter(|| {
+			buffer.clear();
+			for list in &draw_data.draw_lists {
+				buffer.extend_from_slice(ImguiVertex::from_draw_verts(&list.vertices));
+			}
+			criterion::black_box(&buffer);
+		})
+	});
+
+	let mut buffer = Vec::<ImguiArgs>::with_capacity(vertex_count);
+	group.bench_function("per_vertex_conversion", |b| {
+		b.iter(|| {
+			buffer.clear();
+			for list in &draw_data.draw_lists {
+				buffer.extend(list.vertices.iter().map(|v| ImguiArgs::from(*v)));
+			}
+			criterion::black_box(&buffer);
+		})
+	});
+
+	group.finish();
+}
+
+criterion_group!(benches, vertex_upload);
+criterion_main!(benches);
@@ -1,7 +1,7 @@
 extern crate amethyst;
 extern crate amethyst_imgui;
 use amethyst::{
-	assets::{AssetLoaderSystemData, AssetStorage, Handle, Loader},
+	assets::{AssetLoaderSystemData, AssetStorage, Loader},
 	ecs::prelude::*,
 	input::{InputBundle, StringBindings},
 	prelude::*,
@@ -18,16 +18,16 @@ use amethyst::{
 use amethyst_imgui::{imgui::im_str, ImguiStatePtr, RenderImgui};
 
 pub struct DemoSystem {
-	image_handle: Handle<Texture>,
+	image_id: imgui::TextureId,
 }
 impl<'s> amethyst::ecs::System<'s> for DemoSystem {
 	type SystemData = ();
 
 	fn run(&mut self, _: Self::SystemData) {
-		amethyst_imgui::with(|ui| {
+		let _ = amethyst_imgui::with(|ui| {
 			imgui::Window::new(im_str!("Demo Custom Texture")).build(ui, || {
 				ui.text("Hello World");
-				imgui::Image::new(imgui::TextureId::from(self.image_handle.id() as usize), [256.0, 256.0]).build(ui);
+				imgui::Image::new(self.image_id, [256.0, 256.0]).build(ui);
 			});
 		});
 	}
@@ -53,13 +53,13 @@ impl<'a, 'b> SystemDesc<'a, 'b, DemoSystem> for DemoSystemDesc {
 		.unwrap();
 
 		let image_handle = world.exec(|loader: AssetLoaderSystemData<'_, Texture>| loader.load_from_data(TextureData(texture_builder), ()));
-		{
+		let image_id = {
 			let context_mutex = world.fetch::<ImguiStatePtr>();
 			let mut context = context_mutex.lock().unwrap();
-			context.textures.push(image_handle.clone());
-		}
+			context.textures.insert(image_handle)
+		};
 
-		DemoSystem { image_handle }
+		DemoSystem { image_id }
 	}
 }
 
@@ -78,7 +78,7 @@ fn main() -> amethyst::Result<()> {
 				.with_plugin(RenderToWindow::from_config_path(display_config_path)?.with_clear([0.34, 0.36, 0.52, 1.0]))
 				.with_plugin(RenderImgui::<StringBindings>::default()),
 		)?
-		.with_system_desc(DemoSystemDesc::default(), "imgui_use", &[]);
+		.with_system_desc(DemoSystemDesc::default(), "imgui_use", &["imgui_frame"]);
 
 	Application::build("/", Example)?.build(game_data)?.run();
 
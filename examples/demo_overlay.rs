@@ -1,7 +1,6 @@
 extern crate amethyst;
 extern crate amethyst_imgui;
 use amethyst::{
-	ecs::ReadExpect,
 	input::{InputBundle, StringBindings},
 	prelude::*,
 	renderer::{bundle::RenderingBundle, types::DefaultBackend, RenderToWindow},
@@ -12,7 +11,6 @@ use amethyst_imgui::{
 	imgui::{im_str, ImString},
 	RenderImgui,
 };
-use std::sync::{Arc, Mutex};
 
 const DISTANCE: f32 = 10.0;
 
@@ -32,34 +30,33 @@ impl Default for DemoSystem {
 }
 
 impl<'s> amethyst::ecs::System<'s> for DemoSystem {
-	type SystemData = (ReadExpect<'s, Arc<Mutex<amethyst_imgui::ImguiState>>>,);
+	type SystemData = ();
 
-	fn run(&mut self, (context,): Self::SystemData) {
-		let ctx = &mut context.lock().unwrap().context;
-		let io = ctx.io();
+	fn run(&mut self, _: Self::SystemData) {
+		let _ = amethyst_imgui::with(|ui| {
+			let io = ui.io();
 
-		let mut corner = self.corner;
-		let mut open = self.open;
+			let mut corner = self.corner;
+			let mut open = self.open;
 
-		let mut window_pos = [DISTANCE, DISTANCE];
-		let mut window_pos_pivot = [0.0, 0.0];
+			let mut window_pos = [DISTANCE, DISTANCE];
+			let mut window_pos_pivot = [0.0, 0.0];
 
-		if corner != -1 {
-			if (corner & 1) != 0 {
-				window_pos[0] = io.display_size[0] - DISTANCE;
-			}
-			if (corner & 2) != 0 {
-				window_pos[1] = io.display_size[1] - DISTANCE;
-			}
-			if (corner & 1) != 0 {
-				window_pos_pivot[0] = 1.0;
-			}
-			if (corner & 2) != 0 {
-				window_pos_pivot[1] = 1.0;
+			if corner != -1 {
+				if (corner & 1) != 0 {
+					window_pos[0] = io.display_size[0] - DISTANCE;
+				}
+				if (corner & 2) != 0 {
+					window_pos[1] = io.display_size[1] - DISTANCE;
+				}
+				if (corner & 1) != 0 {
+					window_pos_pivot[0] = 1.0;
+				}
+				if (corner & 2) != 0 {
+					window_pos_pivot[1] = 1.0;
+				}
 			}
-		}
 
-		amethyst_imgui::with(|ui| {
 			let title = im_str!("Example: Simple overlay");
 			let mut window = imgui::Window::new(&title)
 				.bg_alpha(0.35)
@@ -119,14 +116,13 @@ fn main() -> amethyst::Result<()> {
 	let display_config_path = app_root.join("examples/display.ron");
 
 	let game_data = GameDataBuilder::default()
-		.with_barrier()
-		.with(DemoSystem::default(), "imgui_use", &[])
 		.with_bundle(InputBundle::<StringBindings>::default())?
 		.with_bundle(
 			RenderingBundle::<DefaultBackend>::new()
 				.with_plugin(RenderToWindow::from_config_path(display_config_path)?.with_clear([0.34, 0.36, 0.52, 1.0]))
 				.with_plugin(RenderImgui::<StringBindings>::default()),
-		)?;
+		)?
+		.with(DemoSystem::default(), "imgui_use", &["imgui_frame"]);
 
 	Application::build("/", Example)?.build(game_data)?.run();
 
@@ -15,7 +15,7 @@ impl<'s> amethyst::ecs::System<'s> for DemoSystem {
 	type SystemData = ();
 
 	fn run(&mut self, _: Self::SystemData) {
-		amethyst_imgui::with(|ui| {
+		let _ = amethyst_imgui::with(|ui| {
 			ui.show_demo_window(&mut true);
 		});
 	}
@@ -30,14 +30,13 @@ fn main() -> amethyst::Result<()> {
 	let display_config_path = app_root.join("examples/display.ron");
 
 	let game_data = GameDataBuilder::default()
-		.with_barrier()
-		.with(DemoSystem::default(), "imgui_use", &[])
 		.with_bundle(InputBundle::<StringBindings>::default())?
 		.with_bundle(
 			RenderingBundle::<DefaultBackend>::new()
 				.with_plugin(RenderToWindow::from_config_path(display_config_path)?.with_clear([0.34, 0.36, 0.52, 1.0]))
 				.with_plugin(RenderImgui::<StringBindings>::default()),
-		)?;
+		)?
+		.with(DemoSystem::default(), "imgui_use", &["imgui_frame"]);
 
 	Application::build("/", Example)?.build(game_data)?.run();
 
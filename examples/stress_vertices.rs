@@ -0,0 +1,74 @@
+extern crate amethyst;
+extern crate amethyst_imgui;
+use amethyst::{
+	input::{InputBundle, StringBindings},
+	prelude::*,
+	renderer::{bundle::RenderingBundle, types::DefaultBackend, RenderToWindow},
+	utils::application_root_dir,
+};
+
+use amethyst_imgui::{imgui, imgui::im_str, RenderImgui};
+
+/// Each filled rectangle is 4 vertices, so this draws 100k of them every frame.
+const RECTS: usize = 25_000;
+
+/// Shows upload throughput by filling a window with small rectangles and printing the frame rate. `benches/vertices.rs`
+/// measures writing its vertices to a buffer without a window.
+#[derive(Default, Clone, Copy)]
+pub struct StressSystem;
+impl<'s> amethyst::ecs::System<'s> for StressSystem {
+	type SystemData = ();
+
+	fn run(&mut self, _: Self::SystemData) {
+		let _ = amethyst_imgui::with(|ui| {
+			imgui::Window::new(im_str!("Stress test"))
+				.size([800.0, 600.0], imgui::Condition::FirstUseEver)
+				.build(ui, || {
+					let io = ui.io();
+					ui.text(format!(
+						"{:.1} fps, {:.2} ms, {} vertices",
+						io.framerate,
+						1000.0 / io.framerate,
+						io.metrics_render_vertices
+					));
+					ui.text(format!(
+						"{:.1} M vertices/s",
+						io.metrics_render_vertices as f32 * io.framerate / 1_000_000.0
+					));
+
+					let origin = ui.cursor_screen_pos();
+					let width = ui.content_region_avail()[0].max(1.0);
+					let columns = (width / 4.0) as usize;
+					let draw_list = ui.get_window_draw_list();
+					for i in 0..RECTS {
+						let x = origin[0] + (i % columns) as f32 * 4.0;
+						let y = origin[1] + (i / columns) as f32 * 4.0;
+						let color = [(i % 255) as f32 / 255.0, 0.5, 1.0 - (i % 255) as f32 / 255.0, 1.0];
+						draw_list.add_rect([x, y], [x + 3.0, y + 3.0], color).filled(true).build();
+					}
+				});
+		});
+	}
+}
+
+struct Example;
+impl SimpleState for Example {}
+
+fn main() -> amethyst::Result<()> {
+	amethyst::start_logger(Default::default());
+	let app_root = application_root_dir()?;
+	let display_config_path = app_root.join("examples/display.ron");
+
+	let game_data = GameDataBuilder::default()
+		.with_bundle(InputBundle::<StringBindings>::default())?
+		.with_bundle(
+			RenderingBundle::<DefaultBackend>::new()
+				.with_plugin(RenderToWindow::from_config_path(display_config_path)?.with_clear([0.34, 0.36, 0.52, 1.0]))
+				.with_plugin(RenderImgui::<StringBindings>::default()),
+		)?
+		.with(StressSystem::default(), "imgui_use", &["imgui_frame"]);
+
+	Application::build("/", Example)?.build(game_data)?.run();
+
+	Ok(())
+}
@@ -0,0 +1,132 @@
+use amethyst::{
+	ecs::World,
+	renderer::{
+		rendy::{command::RenderPassEncoder, factory::Factory, hal},
+		types::Backend,
+	},
+};
+use derivative::Derivative;
+use std::{os::raw::c_void, sync::Mutex};
+
+/// Identifies a callback registered in [ImguiDrawCallbacks].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImguiCallbackId(pub(crate) usize);
+
+/// Custom rendering inserted into an imgui draw list with [add_draw_callback].
+///
+/// `draw` runs inside the imgui render group at the point of the draw list it was added to, with the scissor set to the
+/// clip rectangle of the current window. The imgui pipeline and buffers are bound again afterwards, so callbacks are
+/// free to bind their own, e.g. to draw a 3D preview inside a window.
+pub trait ImguiDrawCallback<B: Backend>: Send + Sync + 'static {
+	/// Called while the render group is prepared, once per use in the finished frame.
+	fn prepare(&mut self, _factory: &Factory<B>, _index: usize, _subpass: hal::pass::Subpass<'_, B>, _world: &World) {}
+
+	fn draw(&mut self, encoder: &mut RenderPassEncoder<'_, B>, index: usize, scissor: hal::pso::Rect, world: &World);
+}
+
+impl<B, F> ImguiDrawCallback<B> for F
+where
+	B: Backend,
+	F: FnMut(&mut RenderPassEncoder<'_, B>, usize, hal::pso::Rect, &World) + Send + Sync + 'static,
+{
+	fn draw(&mut self, encoder: &mut RenderPassEncoder<'_, B>, index: usize, scissor: hal::pso::Rect, world: &World) {
+		(self)(encoder, index, scissor, world)
+	}
+}
+
+/// Resource holding the draw callbacks which can be referenced from imgui draw lists.
+#[derive(Derivative)]
+#[derivative(Default(bound = ""))]
+pub struct ImguiDrawCallbacks<B: Backend> {
+	callbacks: Vec<Option<Box<dyn ImguiDrawCallback<B>>>>,
+}
+
+impl<B: Backend> ImguiDrawCallbacks<B> {
+	pub fn insert(&mut self, callback: impl ImguiDrawCallback<B>) -> ImguiCallbackId {
+		let callback = Some(Box::new(callback) as Box<dyn ImguiDrawCallback<B>>);
+
+		match self.callbacks.iter().position(Option::is_none) {
+			Some(free) => {
+				self.callbacks[free] = callback;
+				ImguiCallbackId(free)
+			},
+			None => {
+				self.callbacks.push(callback);
+				ImguiCallbackId(self.callbacks.len() - 1)
+			},
+		}
+	}
+
+	pub fn remove(&mut self, id: ImguiCallbackId) -> Option<Box<dyn ImguiDrawCallback<B>>> {
+		self.callbacks.get_mut(id.0).and_then(Option::take)
+	}
+
+	pub fn get_mut(&mut self, id: ImguiCallbackId) -> Option<&mut (dyn ImguiDrawCallback<B> + 'static)> {
+		match self.callbacks.get_mut(id.0) {
+			Some(Some(callback)) => Some(callback.as_mut()),
+			_ => None,
+		}
+	}
+}
+
+/// Adds the callback `id` to the draw list of the current window.
+///
+/// Ids which are not registered in [ImguiDrawCallbacks] when the frame is drawn are skipped.
+pub fn add_draw_callback(_ui: &imgui::Ui, id: ImguiCallbackId) {
+	unsafe {
+		imgui::sys::ImDrawList_AddCallback(imgui::sys::igGetWindowDrawList(), Some(draw_callback_marker), callback_tag(id));
+	}
+}
+
+lazy_static::lazy_static! {
+	// Passed as the user data of draw commands added by `add_draw_callback`, indexed by callback id. Every tag is its
+	// own allocation, so its address cannot be the user data of anyone else's callback. The address of the callback
+	// function would not do, as identical code folding can merge it with another empty function.
+	static ref CALLBACK_TAGS: Mutex<Vec<&'static ImguiCallbackId>> = Mutex::new(Vec::new());
+}
+
+fn callback_tag(id: ImguiCallbackId) -> *mut c_void {
+	let mut tags = CALLBACK_TAGS.lock().unwrap();
+	// Slots of `ImguiDrawCallbacks` are reused, so this only grows with the most callbacks registered at once.
+	while tags.len() <= id.0 {
+		let next = ImguiCallbackId(tags.len());
+		tags.push(Box::leak(Box::new(next)));
+	}
+
+	tags[id.0] as *const ImguiCallbackId as *mut c_void
+}
+
+/// The id of a draw command's callback if it was added by [add_draw_callback], going by its user data.
+pub(crate) fn callback_id(user_data: *const c_void) -> Option<ImguiCallbackId> {
+	let tags = CALLBACK_TAGS.lock().unwrap();
+	tags.iter().find(|&&tag| tag as *const ImguiCallbackId as *const c_void == user_data).map(|tag| **tag)
+}
+
+/// Never called; imgui only keeps the user data of a callback command if it has a callback.
+unsafe extern "C" fn draw_callback_marker(_: *const imgui::sys::ImDrawList, _: *const imgui::sys::ImDrawCmd) {}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn tags_map_back_to_their_ids() {
+		let ids = [ImguiCallbackId(3), ImguiCallbackId(0), ImguiCallbackId(7)];
+		let tags = ids.iter().map(|&id| callback_tag(id)).collect::<Vec<_>>();
+
+		for (&id, &tag) in ids.iter().zip(&tags) {
+			assert_eq!(callback_tag(id), tag);
+			assert_eq!(callback_id(tag), Some(id));
+		}
+	}
+
+	#[test]
+	fn foreign_user_data_is_not_a_callback() {
+		callback_tag(ImguiCallbackId(1));
+		let foreign = 5usize;
+
+		assert_eq!(callback_id(std::ptr::null()), None);
+		assert_eq!(callback_id(1 as *const c_void), None);
+		assert_eq!(callback_id(&foreign as *const usize as *const c_void), None);
+	}
+}
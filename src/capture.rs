@@ -0,0 +1,246 @@
+use amethyst::input::{Axis, BindingTypes, Bindings, Button, InputEvent};
+use derivative::Derivative;
+use std::collections::HashMap;
+
+/// What imgui wanted to capture during the last frame, summed over all contexts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImguiCaptureState {
+	pub mouse: bool,
+	pub keyboard: bool,
+	pub text_input: bool,
+}
+
+/// Which capture keeps an input event out of `FilteredInputEvent`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImguiCapture {
+	Never,
+	/// Whether or not imgui captures input, e.g. for actions only meant for the UI.
+	Always,
+	Mouse,
+	Keyboard,
+	MouseOrKeyboard,
+	/// Mouse or keyboard capture depending on the buttons the action, axis or button is bound to; never for
+	/// anything else.
+	FromBindings,
+}
+
+impl ImguiCapture {
+	fn captured(self, capture: &ImguiCaptureState) -> bool {
+		match self {
+			ImguiCapture::Never | ImguiCapture::FromBindings => false,
+			ImguiCapture::Always => true,
+			ImguiCapture::Mouse => capture.mouse,
+			ImguiCapture::Keyboard => capture.keyboard,
+			ImguiCapture::MouseOrKeyboard => capture.mouse || capture.keyboard,
+		}
+	}
+}
+
+/// Decides which input events are passed on as `FilteredInputEvent`s while imgui captures input.
+///
+/// Releases are always passed on, so a button pressed before imgui captured input is not left held down.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""), Clone(bound = ""))]
+pub struct ImguiCapturePolicy<T: BindingTypes> {
+	/// Cursor and mouse motion, mouse buttons and the wheel.
+	pub mouse: ImguiCapture,
+	/// Key presses and typed characters.
+	pub keyboard: ImguiCapture,
+	/// `ButtonPressed`.
+	pub buttons: ImguiCapture,
+	pub actions: ImguiCapture,
+	pub axes: ImguiCapture,
+	/// Controller events and anything else.
+	pub other: ImguiCapture,
+	action_overrides: HashMap<T::Action, ImguiCapture>,
+	axis_overrides: HashMap<T::Axis, ImguiCapture>,
+}
+impl<T: BindingTypes> Default for ImguiCapturePolicy<T> {
+	fn default() -> Self {
+		Self {
+			mouse: ImguiCapture::Mouse,
+			keyboard: ImguiCapture::Keyboard,
+			buttons: ImguiCapture::FromBindings,
+			actions: ImguiCapture::FromBindings,
+			axes: ImguiCapture::FromBindings,
+			other: ImguiCapture::Never,
+			action_overrides: HashMap::new(),
+			axis_overrides: HashMap::new(),
+		}
+	}
+}
+
+impl<T: BindingTypes> ImguiCapturePolicy<T> {
+	/// Overrides `actions` for a single action.
+	pub fn with_action(mut self, action: T::Action, capture: ImguiCapture) -> Self {
+		self.action_overrides.insert(action, capture);
+		self
+	}
+
+	/// Overrides `axes` for a single axis.
+	pub fn with_axis(mut self, axis: T::Axis, capture: ImguiCapture) -> Self {
+		self.axis_overrides.insert(axis, capture);
+		self
+	}
+
+	/// Returns true if `event` should not be passed on.
+	pub fn is_filtered(&self, event: &InputEvent<T>, capture: &ImguiCaptureState, bindings: &Bindings<T>) -> bool {
+		match event {
+			InputEvent::KeyReleased { .. } |
+			InputEvent::MouseButtonReleased(_) |
+			InputEvent::ButtonReleased(_) |
+			InputEvent::ActionReleased(_) => false,
+			InputEvent::CursorMoved { .. } |
+			InputEvent::MouseMoved { .. } |
+			InputEvent::MouseButtonPressed(_) |
+			InputEvent::MouseWheelMoved(_) => self.mouse.captured(capture),
+			InputEvent::KeyPressed { .. } | InputEvent::KeyTyped(_) => self.keyboard.captured(capture),
+			InputEvent::ButtonPressed(button) => match self.buttons {
+				ImguiCapture::FromBindings => button_capture(button).captured(capture),
+				policy => policy.captured(capture),
+			},
+			InputEvent::ActionPressed(action) | InputEvent::ActionWheelMoved(action) => {
+				match self.action_overrides.get(action).copied().unwrap_or(self.actions) {
+					ImguiCapture::FromBindings => bindings
+						.action_bindings(action)
+						.flat_map(|combination| combination.iter())
+						.any(|button| button_capture(button).captured(capture)),
+					policy => policy.captured(capture),
+				}
+			},
+			InputEvent::AxisMoved { axis, .. } => match self.axis_overrides.get(axis).copied().unwrap_or(self.axes) {
+				ImguiCapture::FromBindings => bindings.axis(axis).map_or(false, |axis| axis_captured(axis, capture)),
+				policy => policy.captured(capture),
+			},
+			_ => self.other.captured(capture),
+		}
+	}
+}
+
+fn button_capture(button: &Button) -> ImguiCapture {
+	match button {
+		Button::Key(_) | Button::ScanCode(_) => ImguiCapture::Keyboard,
+		Button::Mouse(_) | Button::MouseWheel(_) => ImguiCapture::Mouse,
+		_ => ImguiCapture::Never,
+	}
+}
+
+fn axis_captured(axis: &Axis, capture: &ImguiCaptureState) -> bool {
+	match axis {
+		Axis::Emulated { pos, neg } => button_capture(pos).captured(capture) || button_capture(neg).captured(capture),
+		Axis::Mouse { .. } | Axis::MouseWheel { .. } => capture.mouse,
+		Axis::Multiple(axes) => axes.iter().any(|axis| axis_captured(axis, capture)),
+		_ => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use amethyst::{
+		input::{ControllerButton, StringBindings},
+		winit::{MouseButton, VirtualKeyCode},
+	};
+
+	const NOTHING: ImguiCaptureState = ImguiCaptureState {
+		mouse: false,
+		keyboard: false,
+		text_input: false,
+	};
+	const MOUSE: ImguiCaptureState = ImguiCaptureState { mouse: true, ..NOTHING };
+	const KEYBOARD: ImguiCaptureState = ImguiCaptureState { keyboard: true, ..NOTHING };
+
+	fn bindings() -> Bindings<StringBindings> {
+		let mut bindings = Bindings::new();
+		bindings.insert_action_binding("jump".to_string(), vec![Button::Key(VirtualKeyCode::Space)]).unwrap();
+		bindings.insert_action_binding("shoot".to_string(), vec![Button::Mouse(MouseButton::Left)]).unwrap();
+		bindings
+			.insert_axis("move".to_string(), Axis::Emulated {
+				pos: Button::Key(VirtualKeyCode::D),
+				neg: Button::Key(VirtualKeyCode::A),
+			})
+			.unwrap();
+		bindings
+	}
+
+	fn key(pressed: bool) -> InputEvent<StringBindings> {
+		if pressed {
+			InputEvent::KeyPressed { key_code: VirtualKeyCode::Space, scancode: 57 }
+		} else {
+			InputEvent::KeyReleased { key_code: VirtualKeyCode::Space, scancode: 57 }
+		}
+	}
+
+	/// Whether each of `events` is filtered by `policy` while imgui captures `capture`.
+	fn filtered(
+		policy: &ImguiCapturePolicy<StringBindings>,
+		capture: ImguiCaptureState,
+		events: &[InputEvent<StringBindings>],
+	) -> Vec<bool> {
+		let bindings = bindings();
+		events.iter().map(|event| policy.is_filtered(event, &capture, &bindings)).collect()
+	}
+
+	#[test]
+	fn mouse_and_keyboard_follow_their_capture() {
+		let policy = ImguiCapturePolicy::default();
+		let events = [key(true), InputEvent::KeyTyped(' '), InputEvent::MouseButtonPressed(MouseButton::Left)];
+		assert_eq!(filtered(&policy, NOTHING, &events), [false, false, false]);
+		assert_eq!(filtered(&policy, KEYBOARD, &events), [true, true, false]);
+		assert_eq!(filtered(&policy, MOUSE, &events), [false, false, true]);
+	}
+
+	#[test]
+	fn releases_always_pass() {
+		let mut policy = ImguiCapturePolicy::default();
+		policy.mouse = ImguiCapture::Always;
+		policy.keyboard = ImguiCapture::Always;
+		policy.buttons = ImguiCapture::Always;
+		policy.actions = ImguiCapture::Always;
+		let events = [
+			key(false),
+			InputEvent::MouseButtonReleased(MouseButton::Left),
+			InputEvent::ButtonReleased(Button::Key(VirtualKeyCode::Space)),
+			InputEvent::ActionReleased("jump".to_string()),
+		];
+		let capture = ImguiCaptureState {
+			mouse: true,
+			keyboard: true,
+			text_input: true,
+		};
+		assert_eq!(filtered(&policy, capture, &events), [false; 4]);
+	}
+
+	#[test]
+	fn actions_and_axes_follow_their_bindings() {
+		let policy = ImguiCapturePolicy::default();
+		let events = [
+			InputEvent::ActionPressed("jump".to_string()),
+			InputEvent::ActionPressed("shoot".to_string()),
+			InputEvent::AxisMoved { axis: "move".to_string(), value: 1.0 },
+			InputEvent::ButtonPressed(Button::Key(VirtualKeyCode::Space)),
+			InputEvent::ButtonPressed(Button::Controller(0, ControllerButton::A)),
+		];
+		assert_eq!(filtered(&policy, KEYBOARD, &events), [true, false, true, true, false]);
+		assert_eq!(filtered(&policy, MOUSE, &events), [false, true, false, false, false]);
+	}
+
+	#[test]
+	fn never_and_always() {
+		let never = ImguiCapturePolicy {
+			mouse: ImguiCapture::Never,
+			keyboard: ImguiCapture::Never,
+			..Default::default()
+		};
+		let always = ImguiCapturePolicy::default().with_action("jump".to_string(), ImguiCapture::Always);
+		let events = [key(true), InputEvent::MouseButtonPressed(MouseButton::Left)];
+		let capture = ImguiCaptureState {
+			mouse: true,
+			keyboard: true,
+			text_input: false,
+		};
+		assert_eq!(filtered(&never, capture, &events), [false, false]);
+		assert_eq!(filtered(&always, NOTHING, &[InputEvent::ActionPressed("jump".to_string())]), [true]);
+		assert_eq!(filtered(&always, NOTHING, &[InputEvent::ActionPressed("shoot".to_string())]), [false]);
+	}
+}
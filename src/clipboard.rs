@@ -0,0 +1,156 @@
+#[cfg(feature = "clipboard")]
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+/// Text storage behind copy and paste in imgui widgets, installed with `ImguiInputSystemDesc::with_clipboard`.
+///
+/// Without one, contexts use the system clipboard when the `clipboard` feature is enabled and an in-process
+/// [ImguiMemoryClipboard] otherwise.
+pub trait ImguiClipboard: Send + 'static {
+	fn get(&mut self) -> Option<String>;
+	fn set(&mut self, text: &str);
+}
+
+/// Clipboard kept in memory. Clones share their contents, so a clone kept around sees what imgui copied.
+#[derive(Clone, Debug, Default)]
+pub struct ImguiMemoryClipboard {
+	text: Arc<Mutex<Option<String>>>,
+}
+
+impl ImguiMemoryClipboard {
+	pub fn new() -> Self { Self::default() }
+}
+
+impl ImguiClipboard for ImguiMemoryClipboard {
+	fn get(&mut self) -> Option<String> { self.text.lock().ok()?.clone() }
+
+	fn set(&mut self, text: &str) {
+		if let Ok(mut current) = self.text.lock() {
+			*current = Some(text.to_owned());
+		}
+	}
+}
+
+/// The operating system's clipboard.
+///
+/// Some platforms tie their clipboard handle to the thread it was created on, e.g. the `NSPasteboard` on macOS, while
+/// imgui calls its clipboard from whichever worker thread runs a UI system. The handle is therefore created and used
+/// on a thread of its own, and this only sends requests to it.
+#[cfg(feature = "clipboard")]
+#[derive(Debug)]
+pub struct ImguiSystemClipboard {
+	requests: mpsc::Sender<ClipboardRequest>,
+}
+
+#[cfg(feature = "clipboard")]
+enum ClipboardRequest {
+	Get(mpsc::Sender<Option<String>>),
+	Set(String),
+}
+
+#[cfg(feature = "clipboard")]
+impl ImguiSystemClipboard {
+	/// Returns `None` if the clipboard is unavailable, e.g. without a display server.
+	pub fn new() -> Option<Self> {
+		use clipboard::ClipboardProvider;
+
+		let (requests, receiver) = mpsc::channel();
+		let (created, creation) = mpsc::channel();
+		std::thread::Builder::new()
+			.name("imgui clipboard".to_string())
+			.spawn(move || {
+				let mut context = match clipboard::ClipboardContext::new() {
+					Ok(context) => context,
+					Err(_) => return created.send(false).unwrap_or(()),
+				};
+				created.send(true).unwrap_or(());
+
+				// Ends once the `ImguiSystemClipboard` is dropped.
+				for request in receiver {
+					match request {
+						ClipboardRequest::Get(reply) => reply.send(context.get_contents().ok()).unwrap_or(()),
+						// imgui has no way to report a failed copy.
+						ClipboardRequest::Set(text) => context.set_contents(text).unwrap_or(()),
+					}
+				}
+			})
+			.ok()?;
+
+		if creation.recv().ok()? {
+			Some(Self { requests })
+		} else {
+			None
+		}
+	}
+}
+
+#[cfg(feature = "clipboard")]
+impl ImguiClipboard for ImguiSystemClipboard {
+	fn get(&mut self) -> Option<String> {
+		let (reply, contents) = mpsc::channel();
+		self.requests.send(ClipboardRequest::Get(reply)).ok()?;
+		contents.recv().ok()?
+	}
+
+	fn set(&mut self, text: &str) { self.requests.send(ClipboardRequest::Set(text.to_owned())).unwrap_or(()) }
+}
+
+pub(crate) type ImguiClipboardPtr = Arc<Mutex<dyn ImguiClipboard>>;
+
+pub(crate) fn default_clipboard() -> ImguiClipboardPtr {
+	#[cfg(feature = "clipboard")]
+	{
+		if let Some(clipboard) = ImguiSystemClipboard::new() {
+			return Arc::new(Mutex::new(clipboard));
+		}
+	}
+	Arc::new(Mutex::new(ImguiMemoryClipboard::new()))
+}
+
+/// Adapts an [ImguiClipboard] to the backend installed on `imgui::Context`.
+pub(crate) struct ClipboardBackend(pub ImguiClipboardPtr);
+
+impl imgui::ClipboardBackend for ClipboardBackend {
+	fn get(&mut self) -> Option<imgui::ImString> { self.0.lock().ok()?.get().map(imgui::ImString::new) }
+
+	fn set(&mut self, value: &imgui::ImStr) {
+		if let Ok(mut clipboard) = self.0.lock() {
+			clipboard.set(value.to_str());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{context::TestState, ImguiContextId};
+
+	#[test]
+	fn memory_clipboard_clones_share_contents() {
+		let mut clipboard = ImguiMemoryClipboard::new();
+		let mut clone = clipboard.clone();
+		assert_eq!(clone.get(), None);
+
+		clipboard.set("copied");
+		assert_eq!(clone.get().as_ref().map(String::as_str), Some("copied"));
+		clone.set("replaced");
+		assert_eq!(clipboard.get().as_ref().map(String::as_str), Some("replaced"));
+	}
+
+	#[test]
+	fn imgui_copies_and_pastes_through_the_backend() {
+		let mut clipboard = ImguiMemoryClipboard::new();
+		let backend: ImguiClipboardPtr = Arc::new(Mutex::new(clipboard.clone()));
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		test.state.update_context(move |context| context.set_clipboard_backend(Box::new(ClipboardBackend(backend))));
+
+		let ui = test.state.begin_frame().unwrap();
+		assert_eq!(ui.clipboard_text(), None);
+		ui.set_clipboard_text(imgui::im_str!("from imgui"));
+		assert_eq!(clipboard.get().as_ref().map(String::as_str), Some("from imgui"));
+
+		clipboard.set("from the game");
+		assert_eq!(ui.clipboard_text().as_ref().map(imgui::ImString::to_str), Some("from the game"));
+		test.state.end_frame().unwrap();
+	}
+}
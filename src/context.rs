@@ -0,0 +1,255 @@
+use std::{
+	cell::Cell,
+	collections::HashMap,
+	panic::AssertUnwindSafe,
+	sync::{Mutex, MutexGuard, PoisonError, Weak},
+};
+
+use amethyst::renderer::bundle::Target;
+
+use crate::{ImguiError, ImguiState, ImguiStatePtr, IMGUI_OFFSCREEN_TARGET};
+
+/// Names an imgui context, each with its own fonts, style, ini file, input and render group.
+///
+/// Everything uses [ImguiContextId::DEFAULT] unless configured otherwise, and only the default context is inserted
+/// into the world as the [ImguiStatePtr] resource.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImguiContextId(pub &'static str);
+
+impl ImguiContextId {
+	pub const DEFAULT: ImguiContextId = ImguiContextId("default");
+
+	/// Name of the system `name` registered for this context, which has to be unique within the dispatcher.
+	pub(crate) fn system_name(self, name: &str) -> String {
+		if self == Self::DEFAULT {
+			name.to_string()
+		} else {
+			format!("{}_{}", name, self.0)
+		}
+	}
+
+	/// File the window layout of this context is saved to unless configured otherwise; contexts sharing a file would
+	/// overwrite each other's layout.
+	pub(crate) fn default_ini_path(self) -> std::path::PathBuf {
+		if self == Self::DEFAULT {
+			"imgui.ini".into()
+		} else {
+			format!("imgui-{}.ini", self.0).into()
+		}
+	}
+
+	/// Render target an offscreen context is drawn into unless configured otherwise: [IMGUI_OFFSCREEN_TARGET] for the
+	/// default context and `imgui_offscreen_<id>` for others, so several offscreen contexts don't share an image.
+	pub fn offscreen_target(self) -> Target {
+		if self == Self::DEFAULT {
+			return IMGUI_OFFSCREEN_TARGET;
+		}
+
+		// `Target::Custom` only takes static names, so each one is leaked once.
+		let mut names = OFFSCREEN_TARGETS.lock().unwrap_or_else(PoisonError::into_inner);
+		Target::Custom(names.entry(self).or_insert_with(|| Box::leak(format!("imgui_offscreen_{}", self.0).into_boxed_str())))
+	}
+}
+impl Default for ImguiContextId {
+	fn default() -> Self { Self::DEFAULT }
+}
+
+/// Which window events the input system of a context feeds into it.
+///
+/// Resizes and DPI changes always reach every context. A context losing its input releases every key and button
+/// imgui still considers held.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImguiInputRouting {
+	/// Every window event.
+	All,
+	/// Only while the context is the one named by the [ImguiInputFocus] resource.
+	Focused,
+	/// No keyboard, mouse, touch or input method events, e.g. for an offscreen UI which only reacts to its cursor.
+	None,
+}
+impl Default for ImguiInputRouting {
+	fn default() -> Self { ImguiInputRouting::All }
+}
+
+impl ImguiInputRouting {
+	pub(crate) fn receives(self, id: ImguiContextId, focus: &ImguiInputFocus) -> bool {
+		match self {
+			ImguiInputRouting::All => true,
+			ImguiInputRouting::Focused => focus.0 == Some(id),
+			ImguiInputRouting::None => false,
+		}
+	}
+}
+
+/// The context receiving input among those using [ImguiInputRouting::Focused], the default context unless changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImguiInputFocus(pub Option<ImguiContextId>);
+impl Default for ImguiInputFocus {
+	fn default() -> Self { ImguiInputFocus(Some(ImguiContextId::DEFAULT)) }
+}
+
+lazy_static::lazy_static! {
+	static ref CONTEXTS: Mutex<HashMap<ImguiContextId, Weak<Mutex<ImguiState>>>> = Mutex::new(HashMap::new());
+	// imgui keeps its current context in a process wide global, so only one context can be used at a time.
+	static ref ACCESS: Mutex<()> = Mutex::new(());
+	static ref OFFSCREEN_TARGETS: Mutex<HashMap<ImguiContextId, &'static str>> = Mutex::new(HashMap::new());
+}
+
+thread_local! {
+	/// Whether this thread holds `ACCESS`, so [lock_imgui] can be nested.
+	static HOLDS_ACCESS: Cell<bool> = Cell::new(false);
+	/// Whether this thread is running a closure passed to [with_context].
+	static IN_FRAME: Cell<bool> = Cell::new(false);
+}
+
+/// Returned by [lock_imgui], releases access to imgui when the outermost one is dropped.
+pub struct ImguiAccess {
+	guard: Option<MutexGuard<'static, ()>>,
+}
+
+impl Drop for ImguiAccess {
+	fn drop(&mut self) {
+		if self.guard.is_some() {
+			HOLDS_ACCESS.with(|holds| holds.set(false));
+		}
+	}
+}
+
+/// Serializes access to imgui; held by the systems of this crate and [with_context] while they use a context.
+///
+/// This must be taken before locking any [ImguiStatePtr] when more than one context exists. A thread already holding
+/// it gets it again right away, so [with] can be called while holding it.
+pub fn lock_imgui() -> ImguiAccess {
+	if HOLDS_ACCESS.with(Cell::get) {
+		return ImguiAccess { guard: None };
+	}
+
+	// Nothing guarded by the lock is left half updated by a panic, so a poisoned lock is as good as any.
+	let guard = ACCESS.lock().unwrap_or_else(PoisonError::into_inner);
+	HOLDS_ACCESS.with(|holds| holds.set(true));
+	ImguiAccess { guard: Some(guard) }
+}
+
+pub(crate) fn register(id: ImguiContextId, state: &ImguiStatePtr) {
+	CONTEXTS.lock().unwrap().insert(id, std::sync::Arc::downgrade(state));
+}
+
+/// The state of the context `id`, if it has been created and not dropped since.
+pub fn state(id: ImguiContextId) -> Option<ImguiStatePtr> { CONTEXTS.lock().unwrap().get(&id).and_then(Weak::upgrade) }
+
+/// Returns true if any context reports `wants_capture` for its `Io`.
+pub(crate) fn any_wants_capture(wants_capture: impl Fn(&imgui::Io) -> bool) -> bool {
+	let states = CONTEXTS.lock().unwrap().values().filter_map(Weak::upgrade).collect::<Vec<_>>();
+	states.iter().any(|state| wants_capture(state.lock().unwrap().io()))
+}
+
+/// Runs `f` with the active frame of the default imgui context.
+///
+/// This locks the [ImguiStatePtr] resource for the duration of `f`, so it must not be called while that lock is
+/// already held. Returns [ImguiError::NoActiveFrame] when no frame is active, e.g. before the render graph is built,
+/// and [ImguiError::Nested] when called from within `f`, as a nested call would switch imgui's current context under
+/// the outer one. Systems calling this have to depend on the `imgui_frame` system, see
+/// [ImguiFrameSystem](crate::ImguiFrameSystem).
+pub fn with<R>(f: impl FnOnce(&imgui::Ui) -> R) -> Result<R, ImguiError> { with_context(ImguiContextId::DEFAULT, f) }
+
+/// Runs `f` with the active frame of the context `id`, see [with].
+pub fn with_context<R>(id: ImguiContextId, f: impl FnOnce(&imgui::Ui) -> R) -> Result<R, ImguiError> {
+	if IN_FRAME.with(Cell::get) {
+		return Err(ImguiError::Nested);
+	}
+
+	let _access = lock_imgui();
+	let state_ptr = state(id).ok_or(ImguiError::Uninitialized)?;
+	let result = {
+		let state = state_ptr.lock().unwrap_or_else(PoisonError::into_inner);
+		let ui = state.ui()?;
+
+		// A panic in `f` is resumed once the locks are released, so it does not poison them for every later frame.
+		IN_FRAME.with(|in_frame| in_frame.set(true));
+		let result = std::panic::catch_unwind(AssertUnwindSafe(|| f(ui)));
+		IN_FRAME.with(|in_frame| in_frame.set(false));
+		result
+	};
+
+	match result {
+		Ok(result) => Ok(result),
+		Err(panic) => std::panic::resume_unwind(panic),
+	}
+}
+
+/// A headless context for tests, holding [lock_imgui] until it is dropped.
+#[cfg(test)]
+pub(crate) struct TestState {
+	pub state: ImguiState,
+	_access: ImguiAccess,
+}
+
+#[cfg(test)]
+impl TestState {
+	pub fn new(id: ImguiContextId) -> Self {
+		let access = lock_imgui();
+		unsafe {
+			imgui::sys::igSetCurrentContext(std::ptr::null_mut());
+		}
+		let mut context = imgui::Context::create();
+		context.set_ini_filename(None);
+		context.io_mut().display_size = [800.0, 600.0];
+		let platform = imgui_winit_support::WinitPlatform::init(&mut context);
+
+		Self {
+			state: ImguiState::new(id, context, platform, Default::default()).unwrap(),
+			_access: access,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Arc;
+
+	/// Registers the state of a [TestState] as the context `id`, with a frame begun.
+	fn registered(id: ImguiContextId) -> (ImguiStatePtr, ImguiAccess) {
+		let TestState { mut state, _access } = TestState::new(id);
+		state.begin_frame().unwrap();
+		let state_ptr = Arc::new(Mutex::new(state));
+		register(id, &state_ptr);
+		(state_ptr, _access)
+	}
+
+	#[test]
+	fn with_while_holding_the_lock() {
+		let id = ImguiContextId("held");
+		let (_state, _access) = registered(id);
+		let _nested = lock_imgui();
+		assert_eq!(with_context(id, |ui| ui.io().display_size).unwrap(), [800.0, 600.0]);
+	}
+
+	#[test]
+	fn nested_frames_are_an_error() {
+		let id = ImguiContextId("nested");
+		let (_state, _access) = registered(id);
+		match with_context(id, |_| with_context(id, |_| ())) {
+			Ok(Err(ImguiError::Nested)) => {},
+			other => panic!("{:?}", other),
+		}
+		assert!(with_context(id, |_| ()).is_ok());
+	}
+
+	#[test]
+	fn panics_do_not_poison_later_frames() {
+		let id = ImguiContextId("panicking");
+		let (state, _access) = registered(id);
+		assert!(std::panic::catch_unwind(|| with_context(id, |_| panic!("broken UI code"))).is_err());
+		assert!(!state.is_poisoned());
+		assert_eq!(with_context(id, |_| 1).unwrap(), 1);
+	}
+
+	#[test]
+	fn offscreen_targets_are_per_context() {
+		assert_eq!(ImguiContextId::DEFAULT.offscreen_target(), IMGUI_OFFSCREEN_TARGET);
+		assert_eq!(ImguiContextId("editor").offscreen_target(), Target::Custom("imgui_offscreen_editor"));
+		assert_eq!(ImguiContextId("editor").offscreen_target(), ImguiContextId("editor").offscreen_target());
+		assert_ne!(ImguiContextId("editor").offscreen_target(), ImguiContextId("hud").offscreen_target());
+	}
+}
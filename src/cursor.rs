@@ -0,0 +1,28 @@
+use crate::ImguiContextId;
+
+/// Who sets the window's mouse cursor while imgui is running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImguiCursorMode {
+	/// imgui changes the window cursor to what it requests, e.g. resize arrows over window borders.
+	Imgui,
+	/// imgui leaves the window cursor alone. Game code is told what it would have set with [ImguiCursorEvent]s.
+	Game,
+	/// imgui draws its own cursor into the UI and hides the window cursor, e.g. for offscreen UIs or fullscreen
+	/// applications without a system cursor.
+	Software,
+}
+impl Default for ImguiCursorMode {
+	fn default() -> Self { ImguiCursorMode::Imgui }
+}
+
+impl ImguiCursorMode {
+	pub(crate) fn changes_window_cursor(self) -> bool { self != ImguiCursorMode::Game }
+}
+
+/// Written to its `EventChannel` whenever the cursor requested by an imgui context changes, in every cursor mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImguiCursorEvent {
+	pub context_id: ImguiContextId,
+	/// `None` when imgui wants the cursor hidden.
+	pub cursor: Option<imgui::MouseCursor>,
+}
@@ -0,0 +1,159 @@
+use amethyst::{
+	input::{BindingTypes, Bindings, ControllerEvent, InputEvent, InputHandler},
+	shrev::EventChannel,
+	winit::{DeviceEvent, ElementState, Event, KeyboardInput, WindowEvent},
+};
+use derivative::Derivative;
+use std::ops::Deref;
+
+use crate::ImguiCaptureState;
+
+/// An `InputHandler` which only sees the input imgui did not capture, for game systems querying input state.
+///
+/// Presses, typed characters, cursor motion and the wheel are left out while imgui captures the keyboard or mouse,
+/// so the cursor position stops updating; releases always get through so nothing stays held down. Bindings are
+/// copied from the `InputHandler` resource.
+///
+/// Controller input is replayed from the `InputHandler`'s events and filtered according to
+/// [ImguiCapturePolicy::other](crate::ImguiCapturePolicy::other), with button releases, connects and disconnects
+/// always getting through.
+#[derive(Derivative)]
+#[derivative(Default(bound = ""))]
+pub struct FilteredInputHandler<T: BindingTypes> {
+	handler: InputHandler<T>,
+	// `InputHandler` reports its events to a channel; nothing reads these.
+	events: EventChannel<InputEvent<T>>,
+}
+
+impl<T: BindingTypes> Deref for FilteredInputHandler<T> {
+	type Target = InputHandler<T>;
+
+	fn deref(&self) -> &InputHandler<T> { &self.handler }
+}
+
+impl<T: BindingTypes> FilteredInputHandler<T> {
+	pub(crate) fn begin_frame(&mut self, bindings: &Bindings<T>) {
+		// Bindings rarely change, so they are only copied when they do.
+		if self.handler.bindings != *bindings {
+			self.handler.bindings = bindings.clone();
+		}
+		self.handler.send_frame_begin();
+	}
+
+	pub(crate) fn send_event(&mut self, event: &Event, capture: &ImguiCaptureState, hidpi: f32) {
+		if !is_captured(event, capture) {
+			self.handler.send_event(event, &mut self.events, hidpi);
+		}
+	}
+
+	/// Controller state only reaches an `InputHandler` through `send_controller_event`, so it is rebuilt from the
+	/// events of the unfiltered handler.
+	pub(crate) fn send_input_event(&mut self, event: &InputEvent<T>, filtered: bool) {
+		let event = match *event {
+			InputEvent::ControllerAxisMoved { which, axis, value } if !filtered => {
+				ControllerEvent::ControllerAxisMoved { which, axis, value }
+			},
+			InputEvent::ControllerButtonPressed { which, button } if !filtered => {
+				ControllerEvent::ControllerButtonPressed { which, button }
+			},
+			InputEvent::ControllerButtonReleased { which, button } => ControllerEvent::ControllerButtonReleased { which, button },
+			InputEvent::ControllerConnected { which } => ControllerEvent::ControllerConnected { which },
+			InputEvent::ControllerDisconnected { which } => ControllerEvent::ControllerDisconnected { which },
+			_ => return,
+		};
+		self.handler.send_controller_event(&event, &mut self.events);
+	}
+}
+
+fn is_captured(event: &Event, capture: &ImguiCaptureState) -> bool {
+	match event {
+		Event::WindowEvent { event, .. } => match event {
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state, .. },
+				..
+			} => *state == ElementState::Pressed && capture.keyboard,
+			WindowEvent::ReceivedCharacter(_) => capture.keyboard,
+			WindowEvent::MouseInput { state, .. } => *state == ElementState::Pressed && capture.mouse,
+			WindowEvent::CursorMoved { .. } | WindowEvent::MouseWheel { .. } => capture.mouse,
+			_ => false,
+		},
+		Event::DeviceEvent {
+			event: DeviceEvent::MouseMotion { .. },
+			..
+		} => capture.mouse,
+		_ => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use amethyst::input::{Axis, Button, ControllerAxis, ControllerButton, StringBindings};
+
+	fn handler() -> FilteredInputHandler<StringBindings> {
+		let mut bindings = Bindings::<StringBindings>::new();
+		bindings.insert_action_binding("jump".to_string(), vec![Button::Controller(0, ControllerButton::A)]).unwrap();
+		bindings
+			.insert_axis("move".to_string(), Axis::Controller {
+				controller_id: 0,
+				axis: ControllerAxis::LeftX,
+				invert: false,
+				dead_zone: 0.0,
+			})
+			.unwrap();
+
+		let mut handler = FilteredInputHandler::default();
+		handler.begin_frame(&bindings);
+		handler.send_input_event(&InputEvent::ControllerConnected { which: 0 }, false);
+		handler
+	}
+
+	fn button(pressed: bool, button: ControllerButton) -> InputEvent<StringBindings> {
+		if pressed {
+			InputEvent::ControllerButtonPressed { which: 0, button }
+		} else {
+			InputEvent::ControllerButtonReleased { which: 0, button }
+		}
+	}
+
+	#[test]
+	fn controller_input_reaches_bindings() {
+		let mut handler = handler();
+		handler.send_input_event(&button(true, ControllerButton::A), false);
+		handler.send_input_event(
+			&InputEvent::ControllerAxisMoved {
+				which: 0,
+				axis: ControllerAxis::LeftX,
+				value: 0.5,
+			},
+			false,
+		);
+
+		assert!(handler.controller_button_is_down(0, ControllerButton::A));
+		assert_eq!(handler.action_is_down("jump"), Some(true));
+		assert!(handler.axis_value("move").unwrap() > 0.0);
+
+		handler.send_input_event(&button(false, ControllerButton::A), false);
+		assert_eq!(handler.action_is_down("jump"), Some(false));
+	}
+
+	#[test]
+	fn captured_controller_input_is_left_out_but_released() {
+		let mut handler = handler();
+		handler.send_input_event(&button(true, ControllerButton::A), true);
+		handler.send_input_event(
+			&InputEvent::ControllerAxisMoved {
+				which: 0,
+				axis: ControllerAxis::LeftX,
+				value: 0.5,
+			},
+			true,
+		);
+		assert_eq!(handler.action_is_down("jump"), Some(false));
+		assert_eq!(handler.axis_value("move"), Some(0.0));
+
+		handler.send_input_event(&button(true, ControllerButton::A), false);
+		handler.send_input_event(&button(false, ControllerButton::A), true);
+		assert!(!handler.controller_button_is_down(0, ControllerButton::A));
+	}
+}
@@ -0,0 +1,432 @@
+use amethyst::{
+	assets::{Asset, AssetStorage, Format, Handle},
+	ecs::VecStorage,
+	utils::application_root_dir,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::HashMap,
+	path::PathBuf,
+	sync::{Arc, Mutex, PoisonError},
+};
+
+/// Raw TTF data loaded through the amethyst `Loader`.
+#[derive(Clone, Debug)]
+pub struct ImguiFontAsset(pub Vec<u8>);
+
+impl Asset for ImguiFontAsset {
+	type Data = Self;
+	type HandleStorage = VecStorage<Handle<Self>>;
+
+	const NAME: &'static str = "imgui::Font";
+}
+
+/// Loads TTF files as [ImguiFontAsset].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImguiTtfFormat;
+
+impl Format<ImguiFontAsset> for ImguiTtfFormat {
+	fn name(&self) -> &'static str { "IMGUI_TTF" }
+
+	fn import_simple(&self, bytes: Vec<u8>) -> Result<ImguiFontAsset, amethyst::Error> { Ok(ImguiFontAsset(bytes)) }
+}
+
+/// Glyph ranges rasterized for a font source.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImguiGlyphRanges {
+	Default,
+	Korean,
+	Japanese,
+	ChineseFull,
+	ChineseSimplifiedCommon,
+	Cyrillic,
+	Thai,
+	/// Pairs of first and last code point, inclusive, e.g. `Custom(vec![0xf000, 0xf2e0])` for the private use area an
+	/// icon font keeps its glyphs in.
+	Custom(Vec<u32>),
+}
+impl Default for ImguiGlyphRanges {
+	fn default() -> Self { ImguiGlyphRanges::Default }
+}
+
+lazy_static::lazy_static! {
+	/// Zero-terminated copies of custom glyph ranges. The atlas keeps pointing at them after it is built, so each
+	/// distinct range is leaked once and shared by every rebuild.
+	static ref CUSTOM_GLYPH_RANGES: Mutex<HashMap<Vec<u32>, &'static [u32]>> = Mutex::new(HashMap::new());
+}
+
+impl ImguiGlyphRanges {
+	pub fn to_imgui(&self) -> Result<imgui::FontGlyphRanges, failure::Error> {
+		Ok(match self {
+			ImguiGlyphRanges::Default => imgui::FontGlyphRanges::default(),
+			ImguiGlyphRanges::Korean => imgui::FontGlyphRanges::korean(),
+			ImguiGlyphRanges::Japanese => imgui::FontGlyphRanges::japanese(),
+			ImguiGlyphRanges::ChineseFull => imgui::FontGlyphRanges::chinese_full(),
+			ImguiGlyphRanges::ChineseSimplifiedCommon => imgui::FontGlyphRanges::chinese_simplified_common(),
+			ImguiGlyphRanges::Cyrillic => imgui::FontGlyphRanges::cyrillic(),
+			ImguiGlyphRanges::Thai => imgui::FontGlyphRanges::thai(),
+			ImguiGlyphRanges::Custom(ranges) => imgui::FontGlyphRanges::from_slice(custom_glyph_ranges(ranges)?),
+		})
+	}
+}
+
+/// Checks `ranges` the way `FontGlyphRanges::from_slice` would panic on, and returns a zero-terminated copy.
+fn custom_glyph_ranges(ranges: &[u32]) -> Result<&'static [u32], failure::Error> {
+	if ranges.is_empty() || ranges.len() % 2 != 0 {
+		return Err(failure::format_err!("Glyph ranges {:x?} are not pairs of first and last code point", ranges));
+	}
+	for range in ranges.chunks(2) {
+		// imgui 0.2 stores glyphs as 16 bit characters.
+		if range[0] == 0 || range[0] > range[1] || range[1] > 0xffff {
+			return Err(failure::format_err!("Invalid glyph range {:x?}", range));
+		}
+	}
+
+	let mut leaked = CUSTOM_GLYPH_RANGES.lock().unwrap_or_else(PoisonError::into_inner);
+	Ok(*leaked.entry(ranges.to_vec()).or_insert_with(|| {
+		let mut terminated = ranges.to_vec();
+		terminated.push(0);
+		Box::leak(terminated.into_boxed_slice())
+	}))
+}
+
+/// Where the TTF data of a font source comes from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ImguiFontData {
+	/// The font embedded in imgui.
+	Default,
+	/// A TTF file, relative to the application root unless absolute.
+	File(PathBuf),
+	/// TTF data already in memory, e.g. from `include_bytes!`.
+	#[serde(skip)]
+	Bytes(Arc<Vec<u8>>),
+	/// A TTF loaded through the amethyst `Loader` with [ImguiTtfFormat].
+	///
+	/// Until the asset has finished loading the embedded font is rasterized in its place; the atlas is rebuilt once
+	/// it is available.
+	#[serde(skip)]
+	Asset(Handle<ImguiFontAsset>),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImguiFontSource {
+	pub data: ImguiFontData,
+	pub size_pixels: f32,
+	pub glyph_ranges: ImguiGlyphRanges,
+	pub oversample_h: i32,
+	pub oversample_v: i32,
+	pub pixel_snap_h: bool,
+	pub glyph_offset: [f32; 2],
+	pub glyph_min_advance_x: f32,
+}
+impl Default for ImguiFontSource {
+	fn default() -> Self {
+		Self {
+			data: ImguiFontData::Default,
+			size_pixels: 13.0,
+			glyph_ranges: ImguiGlyphRanges::Default,
+			oversample_h: 3,
+			oversample_v: 1,
+			pixel_snap_h: false,
+			glyph_offset: [0.0, 0.0],
+			glyph_min_advance_x: 0.0,
+		}
+	}
+}
+
+impl ImguiFontSource {
+	pub fn default_font(size_pixels: f32) -> Self {
+		Self {
+			size_pixels,
+			..Default::default()
+		}
+	}
+
+	pub fn file(path: impl Into<PathBuf>, size_pixels: f32) -> Self {
+		Self {
+			data: ImguiFontData::File(path.into()),
+			size_pixels,
+			..Default::default()
+		}
+	}
+
+	pub fn bytes(bytes: Vec<u8>, size_pixels: f32) -> Self {
+		Self {
+			data: ImguiFontData::Bytes(Arc::new(bytes)),
+			size_pixels,
+			..Default::default()
+		}
+	}
+
+	pub fn asset(handle: Handle<ImguiFontAsset>, size_pixels: f32) -> Self {
+		Self {
+			data: ImguiFontData::Asset(handle),
+			size_pixels,
+			..Default::default()
+		}
+	}
+
+	pub fn with_glyph_ranges(mut self, glyph_ranges: ImguiGlyphRanges) -> Self {
+		self.glyph_ranges = glyph_ranges;
+		self
+	}
+
+	pub fn with_oversample(mut self, oversample_h: i32, oversample_v: i32) -> Self {
+		self.oversample_h = oversample_h;
+		self.oversample_v = oversample_v;
+		self
+	}
+
+	pub fn with_pixel_snap(mut self, pixel_snap_h: bool) -> Self {
+		self.pixel_snap_h = pixel_snap_h;
+		self
+	}
+
+	pub fn with_glyph_offset(mut self, glyph_offset: [f32; 2]) -> Self {
+		self.glyph_offset = glyph_offset;
+		self
+	}
+
+	/// Useful to make icon fonts merged into a text font monospaced.
+	pub fn with_glyph_min_advance_x(mut self, glyph_min_advance_x: f32) -> Self {
+		self.glyph_min_advance_x = glyph_min_advance_x;
+		self
+	}
+
+	fn font_config(&self, scale: f32) -> Result<imgui::FontConfig, failure::Error> {
+		Ok(imgui::FontConfig {
+			size_pixels: self.size_pixels * scale,
+			oversample_h: self.oversample_h,
+			oversample_v: self.oversample_v,
+			pixel_snap_h: self.pixel_snap_h,
+			glyph_offset: self.glyph_offset,
+			glyph_min_advance_x: self.glyph_min_advance_x,
+			glyph_ranges: self.glyph_ranges.to_imgui()?,
+			..imgui::FontConfig::default()
+		})
+	}
+}
+
+/// A named font built from one or more sources.
+///
+/// Every source after the first is merged into the first one, e.g. to add an icon font or extra glyph ranges.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImguiFont {
+	pub name: String,
+	pub sources: Vec<ImguiFontSource>,
+}
+
+/// The fonts added to the imgui font atlas, loadable from RON through amethyst's `Config` trait.
+///
+/// The first font is the default one; when empty, the embedded imgui font is used at 13 pixels.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImguiFontsConfig {
+	pub fonts: Vec<ImguiFont>,
+}
+
+impl ImguiFontsConfig {
+	/// Adds a new font with `source` as its first source.
+	pub fn with_font(mut self, name: impl Into<String>, source: ImguiFontSource) -> Self {
+		self.fonts.push(ImguiFont {
+			name: name.into(),
+			sources: vec![source],
+		});
+		self
+	}
+
+	/// Merges `source` into the most recently added font.
+	pub fn with_merged(mut self, source: ImguiFontSource) -> Self {
+		match self.fonts.last_mut() {
+			Some(font) => font.sources.push(source),
+			None => {
+				self.fonts.push(ImguiFont {
+					name: String::from("default"),
+					sources: vec![ImguiFontSource::default(), source],
+				})
+			},
+		}
+		self
+	}
+
+	/// Returns true if every font asset this config refers to has finished loading.
+	pub(crate) fn assets_loaded(&self, storage: Option<&AssetStorage<ImguiFontAsset>>) -> bool {
+		self.fonts.iter().flat_map(|font| font.sources.iter()).all(|source| match &source.data {
+			ImguiFontData::Asset(handle) => storage.map_or(false, |storage| storage.get(handle).is_some()),
+			_ => true,
+		})
+	}
+
+	/// Clears `atlas` and adds every configured font to it, with pixel sizes multiplied by `scale`. Asset sources which
+	/// have not finished loading are replaced by the embedded font, so font indices stay the same once they are available.
+	///
+	/// Fonts are loaded before the atlas is cleared, so it is left as is when one of them fails to load.
+	pub(crate) fn add_to_atlas(
+		&self,
+		atlas: &mut imgui::FontAtlas,
+		scale: f32,
+		storage: Option<&AssetStorage<ImguiFontAsset>>,
+	) -> Result<(), failure::Error> {
+		// imgui-rs panics on a font without sources, which RON happily deserializes.
+		if let Some(font) = self.fonts.iter().find(|font| font.sources.is_empty()) {
+			return Err(failure::format_err!("imgui font {:?} has no sources", font.name));
+		}
+
+		// Font data has to outlive the `FontSource`s borrowing it, so it is all read up front.
+		let mut files = HashMap::new();
+		let mut configs = Vec::with_capacity(self.fonts.len());
+		for (i, font) in self.fonts.iter().enumerate() {
+			for (j, source) in font.sources.iter().enumerate() {
+				if let ImguiFontData::File(path) = &source.data {
+					let data = std::fs::read(application_root_dir()?.join(path))
+						.map_err(|e| failure::format_err!("Failed to read imgui font {:?}: {}", path, e))?;
+					files.insert((i, j), data);
+				}
+			}
+			configs.push(font.sources.iter().map(|source| source.font_config(scale)).collect::<Result<Vec<_>, _>>()?);
+		}
+		let default_config = ImguiFontSource::default().font_config(scale)?;
+
+		atlas.clear();
+
+		if self.fonts.is_empty() {
+			atlas.add_font(&[imgui::FontSource::DefaultFontData {
+				config: Some(default_config),
+			}]);
+			return Ok(());
+		}
+
+		for ((i, font), configs) in self.fonts.iter().enumerate().zip(configs) {
+			let sources = font
+				.sources
+				.iter()
+				.enumerate()
+				.zip(configs)
+				.map(|((j, source), config)| {
+					let data = match &source.data {
+						ImguiFontData::Default => None,
+						ImguiFontData::File(_) => files.get(&(i, j)).map(Vec::as_slice),
+						ImguiFontData::Bytes(bytes) => Some(bytes.as_slice()),
+						ImguiFontData::Asset(handle) => storage.and_then(|storage| storage.get(handle)).map(|asset| asset.0.as_slice()),
+					};
+
+					match data {
+						Some(data) => imgui::FontSource::TtfData {
+							data,
+							size_pixels: source.size_pixels * scale,
+							config: Some(config),
+						},
+						None => imgui::FontSource::DefaultFontData { config: Some(config) },
+					}
+				})
+				.collect::<Vec<_>>();
+
+			atlas.add_font(&sources);
+		}
+
+		Ok(())
+	}
+}
+
+/// Refers to a font from [ImguiFontsConfig] by its position, which stays valid when the atlas is rebuilt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImguiFontHandle(usize);
+
+impl ImguiFontHandle {
+	pub fn id(self, ui: &imgui::Ui) -> Option<imgui::FontId> { ui.fonts().fonts().get(self.0).copied() }
+
+	/// Pushes the font for the rest of the current window, or until the returned token is popped.
+	pub fn push(self, ui: &imgui::Ui) -> Option<imgui::FontStackToken> { self.id(ui).map(|id| ui.push_font(id)) }
+}
+
+/// Maps font names from [ImguiFontsConfig] to handles which can be pushed from UI code.
+#[derive(Clone, Debug, Default)]
+pub struct ImguiFonts {
+	handles: HashMap<String, ImguiFontHandle>,
+}
+
+impl ImguiFonts {
+	pub fn new(config: &ImguiFontsConfig) -> Self {
+		Self {
+			handles: config
+				.fonts
+				.iter()
+				.enumerate()
+				.map(|(i, font)| (font.name.clone(), ImguiFontHandle(i)))
+				.collect(),
+		}
+	}
+
+	pub fn get(&self, name: &str) -> Option<ImguiFontHandle> { self.handles.get(name).copied() }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{context::TestState, ImguiContextId};
+
+	const HIRAGANA: [u32; 2] = [0x3040, 0x309f];
+
+	#[test]
+	fn custom_glyph_ranges_are_terminated_once() {
+		let ranges = custom_glyph_ranges(&[0x20, 0x7e, 0x3040, 0x309f]).unwrap();
+		assert_eq!(ranges, &[0x20, 0x7e, 0x3040, 0x309f, 0][..]);
+		assert!(std::ptr::eq(ranges, custom_glyph_ranges(&[0x20, 0x7e, 0x3040, 0x309f]).unwrap()));
+	}
+
+	#[test]
+	fn invalid_glyph_ranges() {
+		for ranges in &[&[][..], &[0x20][..], &[0, 0x7e][..], &[0x7e, 0x20][..], &[0x20, 0x1f600][..]] {
+			assert!(custom_glyph_ranges(ranges).is_err(), "{:x?}", ranges);
+		}
+	}
+
+	#[test]
+	fn fonts_without_sources_are_rejected() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		test.state.set_fonts(ron::de::from_str(r#"(fonts: [(name: "icons", sources: [])])"#).unwrap());
+		assert!(test.state.rebuild_fonts(None).is_err());
+	}
+
+	#[test]
+	fn failed_rebuild_keeps_the_previous_fonts() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let state = &mut test.state;
+		state.set_fonts(ImguiFontsConfig::default().with_font("text", ImguiFontSource::default_font(13.0)));
+		state.rebuild_fonts(None).unwrap();
+		let generation = state.font_atlas().generation;
+
+		state.set_fonts(ImguiFontsConfig::default().with_font("missing", ImguiFontSource::file("missing.ttf", 13.0)));
+		assert!(state.needs_font_rebuild(None));
+		assert!(state.rebuild_fonts(None).is_err());
+		assert!(!state.needs_font_rebuild(None));
+
+		assert!(state.font("text").is_some());
+		assert!(state.font("missing").is_none());
+		assert_eq!(state.fonts().fonts[0].name, "text");
+		assert_eq!(state.font_atlas().generation, generation);
+		unsafe {
+			let atlas = (*imgui::sys::igGetIO()).Fonts;
+			assert_eq!((*atlas).Fonts.Size, 1);
+			assert!((*atlas).TexWidth > 0);
+		}
+	}
+
+	#[test]
+	fn custom_ranges_are_merged() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let kana = ImguiFontSource::bytes(include_bytes!("../mplus-1p-regular.ttf").to_vec(), 13.0)
+			.with_glyph_ranges(ImguiGlyphRanges::Custom(HIRAGANA.to_vec()));
+		test.state.set_fonts(ImguiFontsConfig::default().with_font("text", ImguiFontSource::default_font(13.0)).with_merged(kana));
+		test.state.rebuild_fonts(None).unwrap();
+
+		unsafe {
+			let atlas = (*imgui::sys::igGetIO()).Fonts;
+			assert_eq!((*atlas).Fonts.Size, 1);
+			let font = *(*atlas).Fonts.Data;
+			assert!(!imgui::sys::ImFont_FindGlyphNoFallback(font, 0x3042).is_null());
+			assert!(imgui::sys::ImFont_FindGlyphNoFallback(font, 0x30a2).is_null());
+		}
+	}
+}
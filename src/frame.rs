@@ -0,0 +1,252 @@
+use amethyst::{
+	assets::AssetStorage,
+	core::SystemDesc,
+	ecs::{Read, System, SystemData, World, Write},
+	shrev::EventChannel,
+	window::Window,
+};
+use derivative::Derivative;
+use imgui::{internal::RawWrapper, DrawCmd, DrawCmdParams};
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use crate::{
+	callbacks,
+	lock_imgui,
+	FontAtlasTexture,
+	ImguiCallbackId,
+	ImguiContextId,
+	ImguiCursorEvent,
+	ImguiFontAsset,
+	ImguiFonts,
+	ImguiInputFocus,
+	ImguiStatePtr,
+	ImguiStyle,
+	ImguiTextures,
+};
+
+/// An owned copy of the draw lists produced by the last finished imgui frame.
+///
+/// Unlike `imgui::DrawData` this stays valid after the next frame has begun, so the renderer can consume it at any
+/// point after [ImguiFrameSystem] has run.
+#[derive(Clone, Debug, Default)]
+pub struct ImguiDrawData {
+	pub display_pos: [f32; 2],
+	pub display_size: [f32; 2],
+	pub framebuffer_scale: [f32; 2],
+	pub draw_lists: Vec<ImguiDrawList>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ImguiDrawList {
+	pub vertices: Vec<imgui::DrawVert>,
+	pub indices: Vec<imgui::DrawIdx>,
+	pub commands: Vec<ImguiDrawCmd>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ImguiDrawCmd {
+	Elements {
+		count: usize,
+		clip_rect: [f32; 4],
+		texture_id: imgui::TextureId,
+		vtx_offset: usize,
+		idx_offset: usize,
+	},
+	ResetRenderState,
+	/// A callback added with [crate::add_draw_callback].
+	Callback {
+		id: ImguiCallbackId,
+		clip_rect: [f32; 4],
+	},
+}
+
+/// Everything the renderer needs from a finished frame, so it can draw without locking the imgui state.
+#[derive(Derivative)]
+#[derivative(Clone, Debug, Default)]
+pub struct ImguiDrawSnapshot {
+	pub draw_data: ImguiDrawData,
+	/// The texture registry as of the end of the frame.
+	pub textures: ImguiTextures,
+	#[derivative(Debug = "ignore")]
+	pub(crate) font_atlas: Arc<FontAtlasTexture>,
+}
+
+/// The last [ImguiDrawSnapshot] of each context, published by its [ImguiFrameSystem].
+#[derive(Debug, Default)]
+pub struct ImguiDrawSnapshots {
+	snapshots: HashMap<ImguiContextId, Arc<ImguiDrawSnapshot>>,
+}
+
+impl ImguiDrawSnapshots {
+	pub fn get(&self, context_id: ImguiContextId) -> Option<Arc<ImguiDrawSnapshot>> { self.snapshots.get(&context_id).cloned() }
+}
+
+impl ImguiDrawData {
+	pub(crate) fn copy_from(&mut self, draw_data: &imgui::DrawData) {
+		self.display_pos = draw_data.display_pos;
+		self.display_size = draw_data.display_size;
+		self.framebuffer_scale = draw_data.framebuffer_scale;
+
+		let draw_list_count = draw_data.draw_lists().count();
+		self.draw_lists.resize_with(draw_list_count, Default::default);
+
+		for (target, draw_list) in self.draw_lists.iter_mut().zip(draw_data.draw_lists()) {
+			target.vertices.clear();
+			target.vertices.extend_from_slice(draw_list.vtx_buffer());
+			target.indices.clear();
+			target.indices.extend_from_slice(draw_list.idx_buffer());
+			target.commands.clear();
+
+			for draw_cmd in draw_list.commands() {
+				match draw_cmd {
+					DrawCmd::Elements {
+						count,
+						cmd_params:
+							DrawCmdParams {
+								clip_rect,
+								texture_id,
+								vtx_offset,
+								idx_offset,
+								..
+							},
+						..
+					} => target.commands.push(ImguiDrawCmd::Elements {
+						count,
+						clip_rect,
+						texture_id,
+						vtx_offset,
+						idx_offset,
+					}),
+					DrawCmd::ResetRenderState => target.commands.push(ImguiDrawCmd::ResetRenderState),
+					// Our callbacks run when the frame is drawn, any other raw callback is invoked right away.
+					DrawCmd::RawCallback { callback, raw_cmd } => unsafe {
+						if let Some(id) = callbacks::callback_id((*raw_cmd).UserCallbackData) {
+							let clip_rect = (*raw_cmd).ClipRect;
+							target.commands.push(ImguiDrawCmd::Callback {
+								id,
+								clip_rect: [clip_rect.x, clip_rect.y, clip_rect.z, clip_rect.w],
+							});
+						} else {
+							callback(draw_list.raw(), raw_cmd)
+						}
+					},
+				}
+			}
+		}
+	}
+}
+
+/// Begins and ends imgui frames independently of rendering.
+///
+/// Each run ends the frame UI systems have been building, keeps a copy of its draw data for the renderer and
+/// immediately begins the next frame. This works without a window or render graph, e.g. with the `empty` backend.
+///
+/// It is registered as `imgui_frame`, or `imgui_frame_<id>` for contexts other than the default. Systems drawing UI
+/// must list it as a dependency, otherwise the dispatcher is free to run them before or after the frame ends.
+pub struct ImguiFrameSystem {
+	state: ImguiStatePtr,
+	last_frame: Instant,
+	last_cursor: Option<Option<imgui::MouseCursor>>,
+}
+impl<'s> System<'s> for ImguiFrameSystem {
+	type SystemData = (
+		Option<Read<'s, Window>>,
+		Option<Read<'s, AssetStorage<ImguiFontAsset>>>,
+		Write<'s, ImguiFonts>,
+		Option<Read<'s, AssetStorage<ImguiStyle>>>,
+		Write<'s, EventChannel<ImguiCursorEvent>>,
+		Write<'s, ImguiDrawSnapshots>,
+		Read<'s, ImguiInputFocus>,
+	);
+
+	fn run(
+		&mut self,
+		(window, font_storage, mut fonts, style_storage, mut cursor_events, mut snapshots, focus): Self::SystemData,
+	) {
+		let _access = lock_imgui();
+		let state = &mut *self.state.lock().unwrap();
+
+		if let Some(cursor) = state.mouse_cursor() {
+			if self.last_cursor != Some(cursor) {
+				self.last_cursor = Some(cursor);
+				cursor_events.single_write(ImguiCursorEvent {
+					context_id: state.id(),
+					cursor,
+				});
+			}
+		}
+		if let Some(window) = &window {
+			state.prepare_render(window, &focus);
+		}
+		// The renderer is done with the published snapshot, releasing it lets `end_frame` reuse its buffers.
+		snapshots.snapshots.remove(&state.id());
+		if state.is_frame_active() {
+			state.end_frame().unwrap();
+		}
+		snapshots.snapshots.insert(state.id(), state.snapshot());
+		if let Some(window) = &window {
+			state.update_ime_spot(window);
+		}
+
+		// The style and font atlas can only be changed while no frame is active.
+		state.apply_context_updates().unwrap();
+		state.update_style(style_storage.as_ref().map(|storage| &**storage)).unwrap();
+
+		state.update_hidpi_factor();
+		let font_storage = font_storage.as_ref().map(|storage| &**storage);
+		if state.needs_font_rebuild(font_storage) {
+			match state.rebuild_fonts(font_storage) {
+				Ok(()) if state.id() == ImguiContextId::DEFAULT => *fonts = state.font_handles().clone(),
+				Ok(()) => {},
+				Err(e) => log::error!("Failed to rebuild imgui fonts, keeping the previous ones: {}", e),
+			}
+		}
+
+		self.last_frame = state.io_mut().update_delta_time(self.last_frame);
+		if let Some(window) = &window {
+			let (platform, io) = state.platform_and_io();
+			platform.prepare_frame(io, window).unwrap();
+		}
+		if let Some(offscreen) = state.offscreen.clone() {
+			offscreen.apply(state.io_mut());
+		}
+
+		state.begin_frame().unwrap();
+	}
+}
+
+#[derive(Default, Debug)]
+pub struct ImguiFrameSystemDesc {
+	context_id: ImguiContextId,
+}
+
+impl ImguiFrameSystemDesc {
+	/// Drives frames of the context `context_id`, which has to be created by its input system first.
+	pub fn new(context_id: ImguiContextId) -> Self { Self { context_id } }
+}
+
+impl<'a, 'b> SystemDesc<'a, 'b, ImguiFrameSystem> for ImguiFrameSystemDesc {
+	fn build(self, world: &mut World) -> ImguiFrameSystem {
+		<ImguiFrameSystem as System<'_>>::SystemData::setup(world);
+
+		let state_ptr = crate::state(self.context_id).expect("imgui context has not been created");
+
+		// Begin the first frame right away so UI systems have a frame to draw into on the very first dispatch.
+		{
+			let _access = lock_imgui();
+			let mut state = state_ptr.lock().unwrap();
+			if !state.is_frame_active() {
+				if let Some(offscreen) = state.offscreen.clone() {
+					offscreen.apply(state.io_mut());
+				}
+				state.begin_frame().unwrap();
+			}
+		}
+
+		ImguiFrameSystem {
+			state: state_ptr,
+			last_frame: Instant::now(),
+			last_cursor: None,
+		}
+	}
+}
@@ -0,0 +1,210 @@
+use amethyst::input::{BindingTypes, ControllerAxis, ControllerButton, InputEvent};
+use imgui::NavInput;
+use std::collections::{HashMap, HashSet};
+
+/// Maps controller input to imgui's gamepad navigation, enabled with `ImguiInputSystemDesc::with_gamepad`.
+#[derive(Clone, Debug)]
+pub struct ImguiGamepadConfig {
+	/// Nav inputs set while a button is held; a button may drive several of them.
+	pub buttons: Vec<(ControllerButton, NavInput)>,
+	/// Left stick deflection below which it does not navigate, between 0 and 1.
+	pub deadzone: f32,
+	/// Only this controller navigates when set, otherwise all of them do.
+	pub controller: Option<u32>,
+}
+impl Default for ImguiGamepadConfig {
+	fn default() -> Self {
+		Self {
+			buttons: vec![
+				(ControllerButton::A, NavInput::Activate),
+				(ControllerButton::B, NavInput::Cancel),
+				(ControllerButton::Y, NavInput::Input),
+				(ControllerButton::X, NavInput::Menu),
+				(ControllerButton::DPadLeft, NavInput::DpadLeft),
+				(ControllerButton::DPadRight, NavInput::DpadRight),
+				(ControllerButton::DPadUp, NavInput::DpadUp),
+				(ControllerButton::DPadDown, NavInput::DpadDown),
+				(ControllerButton::LeftShoulder, NavInput::FocusPrev),
+				(ControllerButton::LeftShoulder, NavInput::TweakSlow),
+				(ControllerButton::RightShoulder, NavInput::FocusNext),
+				(ControllerButton::RightShoulder, NavInput::TweakFast),
+			],
+			deadzone: 0.2,
+			controller: None,
+		}
+	}
+}
+
+impl ImguiGamepadConfig {
+	/// Replaces every mapping of `button` with `nav_input`.
+	pub fn with_button(mut self, button: ControllerButton, nav_input: NavInput) -> Self {
+		self.buttons.retain(|(mapped, _)| *mapped != button);
+		self.buttons.push((button, nav_input));
+		self
+	}
+
+	pub fn with_deadzone(mut self, deadzone: f32) -> Self {
+		self.deadzone = deadzone;
+		self
+	}
+
+	pub fn with_controller(mut self, controller: Option<u32>) -> Self {
+		self.controller = controller;
+		self
+	}
+}
+
+/// Controller state tracked from input events, written to `Io::nav_inputs` before every frame.
+#[derive(Debug)]
+pub(crate) struct ImguiGamepad {
+	config: ImguiGamepadConfig,
+	buttons: HashSet<(u32, ControllerButton)>,
+	axes: HashMap<(u32, ControllerAxis), f32>,
+}
+
+impl ImguiGamepad {
+	pub fn new(config: ImguiGamepadConfig) -> Self {
+		Self {
+			config,
+			buttons: HashSet::new(),
+			axes: HashMap::new(),
+		}
+	}
+
+	pub fn handle_event<T: BindingTypes>(&mut self, event: &InputEvent<T>) {
+		match *event {
+			InputEvent::ControllerButtonPressed { which, button } => {
+				self.buttons.insert((which, button));
+			},
+			InputEvent::ControllerButtonReleased { which, button } => {
+				self.buttons.remove(&(which, button));
+			},
+			InputEvent::ControllerAxisMoved { which, axis, value } => {
+				self.axes.insert((which, axis), value);
+			},
+			InputEvent::ControllerDisconnected { which } => {
+				self.buttons.retain(|(controller, _)| *controller != which);
+				self.axes.retain(|(controller, _), _| *controller != which);
+			},
+			_ => {},
+		}
+	}
+
+	pub fn update_nav_inputs(&self, io: &mut imgui::Io) {
+		for value in io.nav_inputs.iter_mut() {
+			*value = 0.0;
+		}
+
+		let controller = self.config.controller;
+		let accepted = |which: u32| controller.map_or(true, |controller| controller == which);
+
+		for (button, nav_input) in &self.config.buttons {
+			if self.buttons.iter().any(|(which, down)| accepted(*which) && down == button) {
+				io.nav_inputs[*nav_input as usize] = 1.0;
+			}
+		}
+
+		for ((which, axis), value) in &self.axes {
+			if !accepted(*which) {
+				continue;
+			}
+			// Stick Y axes grow downwards.
+			let (negative, positive) = match axis {
+				ControllerAxis::LeftX => (NavInput::LStickLeft, NavInput::LStickRight),
+				ControllerAxis::LeftY => (NavInput::LStickUp, NavInput::LStickDown),
+				_ => continue,
+			};
+			let magnitude = self.apply_deadzone(value.abs());
+			let nav_input = if *value < 0.0 { negative } else { positive };
+			let current = &mut io.nav_inputs[nav_input as usize];
+			*current = current.max(magnitude);
+		}
+	}
+
+	/// Rescales `magnitude` so navigation starts at 0 right outside the deadzone.
+	fn apply_deadzone(&self, magnitude: f32) -> f32 {
+		let deadzone = self.config.deadzone.max(0.0).min(0.99);
+		((magnitude - deadzone) / (1.0 - deadzone)).max(0.0).min(1.0)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{context::TestState, ImguiContextId};
+	use amethyst::input::StringBindings;
+
+	fn axis(which: u32, axis: ControllerAxis, value: f32) -> InputEvent<StringBindings> {
+		InputEvent::ControllerAxisMoved { which, axis, value }
+	}
+
+	fn button(which: u32, button: ControllerButton, pressed: bool) -> InputEvent<StringBindings> {
+		if pressed {
+			InputEvent::ControllerButtonPressed { which, button }
+		} else {
+			InputEvent::ControllerButtonReleased { which, button }
+		}
+	}
+
+	#[test]
+	fn sticks_are_rescaled_outside_the_deadzone() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let io = test.state.io_mut();
+		let mut gamepad = ImguiGamepad::new(ImguiGamepadConfig::default().with_deadzone(0.2));
+
+		gamepad.handle_event(&axis(0, ControllerAxis::LeftX, 0.1));
+		gamepad.handle_event(&axis(0, ControllerAxis::LeftY, 0.6));
+		gamepad.update_nav_inputs(io);
+		assert_eq!(io.nav_inputs[NavInput::LStickRight as usize], 0.0);
+		assert!((io.nav_inputs[NavInput::LStickDown as usize] - 0.5).abs() < 1e-6);
+		assert_eq!(io.nav_inputs[NavInput::LStickUp as usize], 0.0);
+
+		gamepad.handle_event(&axis(0, ControllerAxis::LeftX, -1.0));
+		gamepad.handle_event(&axis(0, ControllerAxis::RightX, 1.0));
+		gamepad.update_nav_inputs(io);
+		assert_eq!(io.nav_inputs[NavInput::LStickLeft as usize], 1.0);
+		assert_eq!(io.nav_inputs[NavInput::LStickRight as usize], 0.0);
+	}
+
+	#[test]
+	fn buttons_set_their_nav_inputs() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let io = test.state.io_mut();
+		let mut gamepad = ImguiGamepad::new(ImguiGamepadConfig::default().with_button(ControllerButton::Start, NavInput::Menu));
+
+		gamepad.handle_event(&button(0, ControllerButton::A, true));
+		gamepad.handle_event(&button(0, ControllerButton::LeftShoulder, true));
+		gamepad.handle_event(&button(0, ControllerButton::Start, true));
+		gamepad.update_nav_inputs(io);
+		assert_eq!(io.nav_inputs[NavInput::Activate as usize], 1.0);
+		assert_eq!(io.nav_inputs[NavInput::FocusPrev as usize], 1.0);
+		assert_eq!(io.nav_inputs[NavInput::TweakSlow as usize], 1.0);
+		assert_eq!(io.nav_inputs[NavInput::Menu as usize], 1.0);
+		assert_eq!(io.nav_inputs[NavInput::Cancel as usize], 0.0);
+
+		gamepad.handle_event(&button(0, ControllerButton::A, false));
+		gamepad.update_nav_inputs(io);
+		assert_eq!(io.nav_inputs[NavInput::Activate as usize], 0.0);
+		assert_eq!(io.nav_inputs[NavInput::FocusPrev as usize], 1.0);
+
+		gamepad.handle_event(&InputEvent::ControllerDisconnected { which: 0 });
+		gamepad.update_nav_inputs(io);
+		assert!(io.nav_inputs.iter().all(|value| *value == 0.0));
+	}
+
+	#[test]
+	fn other_controllers_are_ignored() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let io = test.state.io_mut();
+		let mut gamepad = ImguiGamepad::new(ImguiGamepadConfig::default().with_controller(Some(1)));
+
+		gamepad.handle_event(&button(0, ControllerButton::A, true));
+		gamepad.handle_event(&axis(0, ControllerAxis::LeftX, 1.0));
+		gamepad.update_nav_inputs(io);
+		assert!(io.nav_inputs.iter().all(|value| *value == 0.0));
+
+		gamepad.handle_event(&button(1, ControllerButton::B, true));
+		gamepad.update_nav_inputs(io);
+		assert_eq!(io.nav_inputs[NavInput::Cancel as usize], 1.0);
+	}
+}
@@ -0,0 +1,116 @@
+use std::{os::raw::c_int, sync::Mutex};
+
+/// Input method composition, written to its `EventChannel` by platform code which receives it from the OS.
+///
+/// winit does not report composition itself, it only sends the committed characters as `ReceivedCharacter`s, so
+/// nothing has to be written when that is enough.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImguiImeEvent {
+	/// Text being composed, replacing the previous preedit text. Empty when composition is cancelled.
+	Preedit(String),
+	/// Finished text which did not arrive as `ReceivedCharacter`s, typed into the focused text field. Ends
+	/// composition.
+	Commit(String),
+}
+
+lazy_static::lazy_static! {
+	// imgui reports the input position through a plain function pointer without any user data.
+	static ref INPUT_POS: Mutex<Option<[f32; 2]>> = Mutex::new(None);
+}
+
+/// Installed as `Io::ime_set_input_screen_pos_fn`, called by imgui when a text field with a different caret position
+/// becomes active.
+pub(crate) unsafe extern "C" fn set_input_screen_pos(x: c_int, y: c_int) {
+	if let Ok(mut pos) = INPUT_POS.lock() {
+		*pos = Some([x as f32, y as f32]);
+	}
+}
+
+/// Takes the position reported by [set_input_screen_pos] since the last call.
+///
+/// Contexts only render while holding `lock_imgui`, so a position taken right after rendering belongs to that context.
+pub(crate) fn take_input_pos() -> Option<[f32; 2]> { INPUT_POS.lock().ok()?.take() }
+
+/// Shows the text being composed at the input position, as imgui 0.2 cannot display it inside a text field.
+pub(crate) fn show_preedit(ui: &imgui::Ui, preedit: &str, position: [f32; 2]) {
+	imgui::Window::new(imgui::im_str!("##ime_preedit"))
+		.position(position, imgui::Condition::Always)
+		.flags(
+			imgui::WindowFlags::NO_DECORATION |
+				imgui::WindowFlags::NO_INPUTS |
+				imgui::WindowFlags::ALWAYS_AUTO_RESIZE |
+				imgui::WindowFlags::NO_SAVED_SETTINGS |
+				imgui::WindowFlags::NO_FOCUS_ON_APPEARING |
+				imgui::WindowFlags::NO_NAV,
+		)
+		.build(ui, || ui.text(preedit));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{context::TestState, ImguiContextId, ImguiState};
+
+	fn queued_characters() -> i32 { unsafe { (*imgui::sys::igGetIO()).InputQueueCharacters.Size } }
+
+	fn preedit(text: &str) -> ImguiImeEvent { ImguiImeEvent::Preedit(text.to_string()) }
+
+	/// Vertices drawn by a frame with nothing but the preedit window.
+	fn preedit_vertices(state: &mut ImguiState) -> usize {
+		state.begin_frame().unwrap();
+		state.end_frame().unwrap().draw_lists.iter().map(|list| list.vertices.len()).sum()
+	}
+
+	#[test]
+	fn composition_is_committed() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let state = &mut test.state;
+
+		for text in &["k", "か", "かn", "かん"] {
+			state.handle_ime_event(&preedit(text));
+			assert_eq!(state.ime_preedit(), *text);
+		}
+		assert_eq!(queued_characters(), 0);
+
+		state.handle_ime_event(&ImguiImeEvent::Commit("漢".to_string()));
+		assert_eq!(state.ime_preedit(), "");
+		assert_eq!(queued_characters(), 1);
+	}
+
+	#[test]
+	fn cancelled_composition_types_nothing() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let state = &mut test.state;
+
+		state.handle_ime_event(&preedit("ni"));
+		state.handle_ime_event(&preedit(""));
+		assert_eq!(state.ime_preedit(), "");
+		assert_eq!(queued_characters(), 0);
+
+		// A new composition after the cancelled one starts from scratch.
+		state.handle_ime_event(&preedit("ha"));
+		state.handle_ime_event(&ImguiImeEvent::Commit("は".to_string()));
+		assert_eq!(state.ime_preedit(), "");
+		assert_eq!(queued_characters(), 1);
+	}
+
+	#[test]
+	fn commit_without_preedit() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		test.state.handle_ime_event(&ImguiImeEvent::Commit("äb".to_string()));
+		assert_eq!(queued_characters(), 2);
+	}
+
+	#[test]
+	fn preedit_is_shown_at_the_input_position() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let state = &mut test.state;
+		state.ime_position = Some([100.0, 100.0]);
+
+		assert_eq!(preedit_vertices(state), 0);
+		state.handle_ime_event(&preedit("ka"));
+		assert!(preedit_vertices(state) > 0);
+		state.handle_ime_event(&preedit(""));
+		assert_eq!(preedit_vertices(state), 0);
+	}
+}
@@ -1,205 +1,1289 @@
 #![allow(clippy::type_complexity, dead_code)]
 
+mod callbacks;
+mod capture;
+mod clipboard;
+mod context;
+mod cursor;
+mod filtered;
+mod fonts;
+mod frame;
+mod gamepad;
+mod ime;
+mod offscreen;
 mod pass;
+mod style;
+mod textures;
+mod touch;
 
+pub use callbacks::{add_draw_callback, ImguiCallbackId, ImguiDrawCallback, ImguiDrawCallbacks};
+pub use capture::{ImguiCapture, ImguiCapturePolicy, ImguiCaptureState};
+#[cfg(feature = "clipboard")]
+pub use clipboard::ImguiSystemClipboard;
+pub use clipboard::{ImguiClipboard, ImguiMemoryClipboard};
+pub use context::{lock_imgui, state, with, with_context, ImguiAccess, ImguiContextId, ImguiInputFocus, ImguiInputRouting};
+pub use cursor::{ImguiCursorEvent, ImguiCursorMode};
+pub use filtered::FilteredInputHandler;
+pub use fonts::{
+	ImguiFont,
+	ImguiFontAsset,
+	ImguiFontData,
+	ImguiFontHandle,
+	ImguiFontSource,
+	ImguiFonts,
+	ImguiFontsConfig,
+	ImguiGlyphRanges,
+	ImguiTtfFormat,
+};
+pub use frame::{
+	ImguiDrawCmd,
+	ImguiDrawData,
+	ImguiDrawList,
+	ImguiDrawSnapshot,
+	ImguiDrawSnapshots,
+	ImguiFrameSystem,
+	ImguiFrameSystemDesc,
+};
+pub use gamepad::ImguiGamepadConfig;
+pub use ime::ImguiImeEvent;
 pub use imgui;
-pub use pass::DrawImguiDesc;
+pub use imgui_winit_support::HiDpiMode;
+pub use offscreen::{raycast_uv, ImguiOffscreen, IMGUI_OFFSCREEN_TARGET};
+pub use pass::{DrawImguiDesc, ImguiColorSpace, ImguiIndexType, ImguiVertex};
+pub use style::{show_style_editor, ImguiStyle, ImguiStylePreset};
+pub use textures::ImguiTextures;
+pub use touch::ImguiTouchConfig;
 
 use amethyst::{
-	assets::Handle,
-	core::SystemDesc,
-	ecs::{DispatcherBuilder, Read, ReadExpect, System, SystemData, World, Write},
+	assets::{AssetStorage, Handle, Processor},
+	core::{SystemBundle, SystemDesc},
+	ecs::{DispatcherBuilder, Read, System, SystemData, World, Write},
 	error::Error,
-	input::{BindingTypes, InputEvent},
+	input::{BindingTypes, InputEvent, InputHandler},
 	renderer::{
-		bundle::{RenderOrder, RenderPlan, RenderPlugin, Target},
-		rendy::{factory::Factory, graph::render::RenderGroupDesc},
+		bundle::{ImageOptions, OutputColor, RenderOrder, RenderPlan, RenderPlugin, Target, TargetImage, TargetPlanOutputs},
+		rendy::{
+			factory::Factory,
+			graph::{render::RenderGroupDesc, NodeDesc},
+			hal,
+		},
 		types::Backend,
 		Texture,
 	},
 	shrev::{EventChannel, ReaderId},
+	utils::application_root_dir,
 	window::Window,
-	winit::{Event, WindowEvent},
+	winit::{dpi::LogicalPosition, ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
 };
+use clipboard::ImguiClipboardPtr;
 use derivative::Derivative;
-use imgui_winit_support::{HiDpiMode, WinitPlatform};
-use std::sync::{Arc, Mutex};
+use failure::Fail;
+use gamepad::ImguiGamepad;
+use imgui_winit_support::WinitPlatform;
+use offscreen::ImguiOffscreenCopyDesc;
+use touch::ImguiTouch;
+use std::{
+	path::PathBuf,
+	sync::{Arc, Mutex},
+};
 
 pub type ImguiStatePtr = Arc<Mutex<ImguiState>>;
 
+#[derive(Debug, Fail)]
+pub enum ImguiError {
+	#[fail(display = "imgui has not been initialized")]
+	Uninitialized,
+	#[fail(display = "no imgui frame is active")]
+	NoActiveFrame,
+	#[fail(display = "an imgui frame is already active")]
+	FrameActive,
+	#[fail(display = "imgui frames cannot be used from within each other")]
+	Nested,
+}
+
+/// An active frame together with the context it borrows.
+///
+/// imgui-rs only hands out a `Ui` borrowing the whole `Context`, so the context moves in here for the duration of the
+/// frame. The `Ui` is only taken out to render it in `end_frame`.
+#[ouroboros::self_referencing]
+struct ImguiFrame {
+	context: imgui::Context,
+	#[borrows(mut context)]
+	#[covariant]
+	ui: Option<imgui::Ui<'this>>,
+}
+
 pub struct ImguiState {
-	pub context: imgui::Context,
-	pub textures: Vec<Handle<Texture>>,
+	/// The context between frames, moved into `frame` while one is active.
+	context: Option<imgui::Context>,
+	frame: Option<ImguiFrame>,
+	raw_context: *mut imgui::sys::ImGuiContext,
+	pub(crate) platform: WinitPlatform,
+	id: ImguiContextId,
+	snapshot: Arc<ImguiDrawSnapshot>,
+	fonts: ImguiFontsConfig,
+	/// Set by `set_fonts`, replaces `fonts` once the atlas has been rebuilt from it.
+	pending_fonts: Option<ImguiFontsConfig>,
+	font_handles: ImguiFonts,
+	font_scale: f32,
+	hidpi_factor: f32,
+	fonts_dirty: bool,
+	fonts_waiting: bool,
+	font_atlas: Arc<FontAtlasTexture>,
+	pub textures: ImguiTextures,
+	pub offscreen: Option<ImguiOffscreen>,
+	style: Option<ImguiStyle>,
+	style_asset: Option<Handle<ImguiStyle>>,
+	cursor_mode: ImguiCursorMode,
+	/// Whether the input system fed window input into the context during its last run, see [ImguiInputRouting].
+	receives_input: bool,
+	ime_preedit: String,
+	ime_position: Option<[f32; 2]>,
+	ime_position_changed: bool,
+	context_updates: Vec<Box<dyn FnOnce(&mut imgui::Context) + Send>>,
 }
 unsafe impl Send for ImguiState {}
 
+impl Drop for ImguiState {
+	fn drop(&mut self) {
+		// Ending an unfinished frame acts on imgui's current context, which might belong to another state. With more
+		// than one context, states have to be dropped while holding `lock_imgui` like any other use.
+		self.make_current();
+		self.frame = None;
+	}
+}
+
+/// RGBA32 pixels of the built font atlas, kept so the renderer can upload them while a frame is active.
+///
+/// `generation` changes every time the atlas is rebuilt.
+#[derive(Default)]
+pub(crate) struct FontAtlasTexture {
+	pub width: u32,
+	pub height: u32,
+	pub data: Vec<u8>,
+	pub generation: u64,
+}
+
+impl ImguiState {
+	/// Takes ownership of `context`, which has to be imgui's current context.
+	pub fn new(
+		id: ImguiContextId,
+		context: imgui::Context,
+		platform: WinitPlatform,
+		fonts: ImguiFontsConfig,
+	) -> Result<Self, failure::Error> {
+		let hidpi_factor = context.io().display_framebuffer_scale[0];
+		let mut state = Self {
+			context: Some(context),
+			frame: None,
+			raw_context: unsafe { imgui::sys::igGetCurrentContext() },
+			platform,
+			id,
+			snapshot: Arc::default(),
+			font_handles: ImguiFonts::new(&fonts),
+			fonts,
+			pending_fonts: None,
+			font_scale: 1.0,
+			hidpi_factor,
+			fonts_dirty: true,
+			fonts_waiting: false,
+			font_atlas: Arc::default(),
+			textures: ImguiTextures::default(),
+			offscreen: None,
+			style: None,
+			style_asset: None,
+			cursor_mode: ImguiCursorMode::default(),
+			receives_input: true,
+			ime_preedit: String::new(),
+			ime_position: None,
+			ime_position_changed: false,
+			context_updates: Vec::new(),
+		};
+		state.rebuild_fonts(None)?;
+
+		Ok(state)
+	}
+
+	pub fn id(&self) -> ImguiContextId { self.id }
+
+	/// Makes this imgui's current context. Every method of `ImguiState` which calls into imgui does so first, but
+	/// with more than one context, access has to be serialized with [lock_imgui].
+	pub fn make_current(&self) {
+		unsafe {
+			imgui::sys::igSetCurrentContext(self.raw_context);
+		}
+	}
+
+	/// The context, which is only available between frames; use [ImguiState::ui] while a frame is active.
+	pub fn context(&self) -> Result<&imgui::Context, ImguiError> {
+		self.make_current();
+		self.context.as_ref().ok_or(ImguiError::FrameActive)
+	}
+
+	/// Mutable access to the context, which is only available between frames.
+	fn context_mut(&mut self) -> Result<&mut imgui::Context, ImguiError> {
+		self.make_current();
+		self.context.as_mut().ok_or(ImguiError::FrameActive)
+	}
+
+	/// Runs `f` with mutable access to the context between frames, once the active frame has ended and before the
+	/// next one begins. Runs it right away if no frame is active.
+	pub fn update_context(&mut self, f: impl FnOnce(&mut imgui::Context) + Send + 'static) {
+		match self.context_mut() {
+			Ok(context) => f(context),
+			Err(_) => self.context_updates.push(Box::new(f)),
+		}
+	}
+
+	/// Runs the functions queued with [ImguiState::update_context]; only possible between frames.
+	pub(crate) fn apply_context_updates(&mut self) -> Result<(), ImguiError> {
+		self.make_current();
+		let context = self.context.as_mut().ok_or(ImguiError::FrameActive)?;
+		for update in self.context_updates.drain(..) {
+			update(context);
+		}
+
+		Ok(())
+	}
+
+	pub fn io(&self) -> &imgui::Io {
+		self.make_current();
+		// `Io` lives inside the raw imgui context rather than in `imgui::Context`, so it is reachable during a frame too.
+		unsafe { &*(imgui::sys::igGetIO() as *const imgui::Io) }
+	}
+
+	pub fn io_mut(&mut self) -> &mut imgui::Io {
+		self.make_current();
+		// `Io` lives inside the raw imgui context rather than in `imgui::Context`, so writing it does not alias the
+		// shared borrow held by an active frame.
+		unsafe { &mut *(imgui::sys::igGetIO() as *mut imgui::Io) }
+	}
+
+	/// The window platform of this context together with its `Io`, which most platform calls need.
+	pub(crate) fn platform_and_io(&mut self) -> (&mut WinitPlatform, &mut imgui::Io) {
+		self.make_current();
+		(&mut self.platform, unsafe { &mut *(imgui::sys::igGetIO() as *mut imgui::Io) })
+	}
+
+	/// Lets the window platform update the cursor for the active frame before it ends, if this context is the one in
+	/// charge of the window cursor: it has the [ImguiInputFocus], receives window input and is not drawn offscreen.
+	pub(crate) fn prepare_render(&mut self, window: &Window, focus: &ImguiInputFocus) {
+		if !self.cursor_mode.changes_window_cursor() ||
+			!self.receives_input ||
+			self.offscreen.is_some() ||
+			focus.0 != Some(self.id)
+		{
+			return;
+		}
+		self.make_current();
+		if let Some(ui) = self.frame.as_ref().and_then(|frame| frame.borrow_ui().as_ref()) {
+			self.platform.prepare_render(ui, window);
+		}
+	}
+
+	pub fn cursor_mode(&self) -> ImguiCursorMode { self.cursor_mode }
+
+	/// Text being composed with an input method, see [ImguiImeEvent].
+	pub fn ime_preedit(&self) -> &str { &self.ime_preedit }
+
+	/// Where imgui last asked for input method candidates to appear, in imgui's coordinates.
+	pub fn ime_position(&self) -> Option<[f32; 2]> { self.ime_position }
+
+	pub(crate) fn handle_ime_event(&mut self, event: &ImguiImeEvent) {
+		match event {
+			ImguiImeEvent::Preedit(text) => self.ime_preedit = text.clone(),
+			ImguiImeEvent::Commit(text) => {
+				self.ime_preedit.clear();
+				let io = self.io_mut();
+				for c in text.chars() {
+					io.add_input_character(c);
+				}
+			},
+		}
+	}
+
+	/// Moves the window's input method candidates to where imgui last asked for them.
+	pub(crate) fn update_ime_spot(&mut self, window: &Window) {
+		if !self.ime_position_changed || self.offscreen.is_some() {
+			return;
+		}
+		self.ime_position_changed = false;
+		if let Some(position) = self.ime_position {
+			// imgui's coordinates are scaled by the framebuffer scale, which differs from the DPI factor in some
+			// `HiDpiMode`s.
+			let scale = f64::from(self.io().display_framebuffer_scale[0]) / window.get_hidpi_factor();
+			window.set_ime_spot(LogicalPosition::new(f64::from(position[0]) * scale, f64::from(position[1]) * scale));
+		}
+	}
+
+	/// The cursor requested by the active frame, `None` if no frame is active.
+	pub fn mouse_cursor(&self) -> Option<Option<imgui::MouseCursor>> { self.ui().ok().map(|ui| ui.mouse_cursor()) }
+
+	pub fn is_frame_active(&self) -> bool { self.frame.is_some() }
+
+	/// Window positions, sizes and docking layout in imgui's ini format, as they would be saved to the ini file.
+	pub fn save_ini_settings(&self) -> String {
+		self.make_current();
+		unsafe { std::ffi::CStr::from_ptr(imgui::sys::igSaveIniSettingsToMemory(std::ptr::null_mut())) }
+			.to_string_lossy()
+			.into_owned()
+	}
+
+	/// Loads settings saved with [ImguiState::save_ini_settings] before the next frame begins. They only apply to
+	/// windows which have not been shown yet, so this is mostly useful from `ImguiInputSystemDesc::with_ini_settings`.
+	pub fn load_ini_settings(&mut self, settings: &str) {
+		let settings = settings.to_string();
+		self.update_context(move |context| context.load_ini_settings(&settings));
+	}
+
+	/// The currently active frame, if any.
+	pub fn ui(&self) -> Result<&imgui::Ui<'_>, ImguiError> {
+		self.make_current();
+		self.frame.as_ref().and_then(|frame| frame.borrow_ui().as_ref()).ok_or(ImguiError::NoActiveFrame)
+	}
+
+	/// Starts a new imgui frame, which stays active until [ImguiState::end_frame] is called.
+	///
+	/// The context is only reachable through the returned `Ui` or [ImguiState::ui] until the frame ends.
+	pub fn begin_frame(&mut self) -> Result<&imgui::Ui<'_>, ImguiError> {
+		self.make_current();
+		let context = self.context.take().ok_or(ImguiError::FrameActive)?;
+		self.frame = Some(ImguiFrame::new(context, |context| Some(context.frame())));
+
+		self.ui()
+	}
+
+	/// Ends the active frame and keeps a copy of its draw data for the renderer.
+	pub fn end_frame(&mut self) -> Result<&ImguiDrawData, ImguiError> {
+		let mut frame = self.frame.take().ok_or(ImguiError::NoActiveFrame)?;
+		self.make_current();
+
+		// Buffers of the previous snapshot are reused unless the renderer still holds on to it.
+		let snapshot = Arc::make_mut(&mut self.snapshot);
+		let (preedit, ime_position) = (&self.ime_preedit, self.ime_position);
+		frame.with_ui_mut(|ui| {
+			let ui = ui.take().expect("the frame is only rendered once");
+			if let Some(position) = ime_position.filter(|_| !preedit.is_empty()) {
+				ime::show_preedit(&ui, preedit, position);
+			}
+			ime::take_input_pos();
+			snapshot.draw_data.copy_from(ui.render());
+		});
+		self.context = Some(frame.into_heads().context);
+		if let Some(position) = ime::take_input_pos() {
+			self.ime_position = Some(position);
+			self.ime_position_changed = true;
+		}
+		if snapshot.textures.generation() != self.textures.generation() {
+			snapshot.textures = self.textures.clone();
+		}
+		snapshot.font_atlas = self.font_atlas.clone();
+
+		Ok(&self.snapshot.draw_data)
+	}
+
+	/// Draw data of the last finished frame.
+	pub fn draw_data(&self) -> &ImguiDrawData { &self.snapshot.draw_data }
+
+	/// Everything the renderer needs from the last finished frame, see [ImguiDrawSnapshots].
+	pub fn snapshot(&self) -> Arc<ImguiDrawSnapshot> { self.snapshot.clone() }
+
+	pub(crate) fn font_atlas(&self) -> &FontAtlasTexture { &self.font_atlas }
+
+	pub fn fonts(&self) -> &ImguiFontsConfig { &self.fonts }
+
+	/// Handle of the configured font `name`, as of the last atlas rebuild.
+	pub fn font(&self, name: &str) -> Option<ImguiFontHandle> { self.font_handles.get(name) }
+
+	pub(crate) fn font_handles(&self) -> &ImguiFonts { &self.font_handles }
+
+	/// Applies `style` before the next frame begins, replacing any style asset.
+	pub fn set_style(&mut self, style: ImguiStyle) {
+		self.style = Some(style);
+		self.style_asset = None;
+	}
+
+	/// Applies the style asset once it has loaded, and again every time it is reloaded.
+	pub fn set_style_asset(&mut self, handle: Handle<ImguiStyle>) { self.style_asset = Some(handle); }
+
+	/// Applies a style set since the last frame, or the style asset if it changed; only possible between frames.
+	pub(crate) fn update_style(&mut self, storage: Option<&AssetStorage<ImguiStyle>>) -> Result<(), ImguiError> {
+		let style = match (&self.style_asset, storage) {
+			(Some(handle), Some(storage)) => storage.get(handle).filter(|style| Some(*style) != self.style.as_ref()).cloned(),
+			(Some(_), None) => None,
+			(None, _) => self.style.take(),
+		};
+
+		if let Some(style) = style {
+			style.apply(self.context_mut()?.style_mut());
+			// Kept to detect reloads of the asset.
+			if self.style_asset.is_some() {
+				self.style = Some(style);
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Replaces the configured fonts once the atlas has been rebuilt from them before the next frame begins. If one of
+	/// them fails to load, the error is logged and the previous fonts are kept.
+	pub fn set_fonts(&mut self, fonts: ImguiFontsConfig) {
+		self.pending_fonts = Some(fonts);
+		self.request_font_rebuild();
+	}
+
+	pub fn font_scale(&self) -> f32 { self.font_scale }
+
+	/// Scales the pixel size of every configured font, e.g. to change the UI scale at runtime. Unlike
+	/// `Io::font_global_scale` the glyphs are rasterized at the new size, so text stays sharp.
+	pub fn set_font_scale(&mut self, font_scale: f32) {
+		if (self.font_scale - font_scale).abs() > std::f32::EPSILON {
+			self.font_scale = font_scale;
+			self.request_font_rebuild();
+		}
+	}
+
+	/// Rebuilds the font atlas and uploads it as a new texture before the next frame begins.
+	pub fn request_font_rebuild(&mut self) { self.fonts_dirty = true; }
+
+	/// Fonts are rasterized at the framebuffer scale set by `WinitPlatform`, which changes with the window's DPI. It
+	/// is always 1 for offscreen images, whatever `WinitPlatform` set since the last frame.
+	pub(crate) fn update_hidpi_factor(&mut self) {
+		let hidpi_factor = if self.offscreen.is_some() { 1.0 } else { self.io().display_framebuffer_scale[0] };
+		if (self.hidpi_factor - hidpi_factor).abs() > std::f32::EPSILON {
+			self.hidpi_factor = hidpi_factor;
+			self.request_font_rebuild();
+		}
+	}
+
+	/// Returns true if a rebuild was requested, or if font assets missing from the last build have finished loading.
+	pub(crate) fn needs_font_rebuild(&self, storage: Option<&AssetStorage<ImguiFontAsset>>) -> bool {
+		self.fonts_dirty || (self.fonts_waiting && self.fonts.assets_loaded(storage))
+	}
+
+	/// Rebuilds the font atlas from the configured fonts; this is only possible between frames. On error the previous
+	/// atlas and fonts are kept, and the rebuild is not retried until it is requested again.
+	pub(crate) fn rebuild_fonts(&mut self, storage: Option<&AssetStorage<ImguiFontAsset>>) -> Result<(), failure::Error> {
+		self.make_current();
+		let context = self.context.as_mut().ok_or(ImguiError::FrameActive)?;
+
+		self.fonts_dirty = false;
+		let fonts = match self.pending_fonts.take() {
+			Some(fonts) => fonts,
+			None => self.fonts.clone(),
+		};
+		let fonts_waiting = !fonts.assets_loaded(storage);
+		{
+			let mut atlas = context.fonts();
+			fonts.add_to_atlas(&mut atlas, self.font_scale * self.hidpi_factor, storage)?;
+
+			let texture = atlas.build_rgba32_texture();
+			self.font_atlas = Arc::new(FontAtlasTexture {
+				width: texture.width,
+				height: texture.height,
+				data: texture.data.to_vec(),
+				generation: self.font_atlas.generation + 1,
+			});
+			atlas.tex_id = imgui::TextureId::from(std::usize::MAX);
+		}
+		self.fonts_waiting = fonts_waiting;
+		self.font_handles = ImguiFonts::new(&fonts);
+		self.fonts = fonts;
+
+		// Glyphs are rasterized in physical pixels but laid out in logical ones.
+		context.io_mut().font_global_scale = 1.0 / self.hidpi_factor;
+
+		Ok(())
+	}
+}
+
 pub struct FilteredInputEvent<T: BindingTypes>(pub InputEvent<T>);
 
+/// Feeds window events into one imgui context.
+///
+/// The input system of the first context created also writes [FilteredInputEvent]s, leaving out input captured by
+/// any of the contexts according to its [ImguiCapturePolicy], and updates the [ImguiCaptureState] and
+/// [FilteredInputHandler] resources.
 pub struct ImguiInputSystem<T: BindingTypes> {
-	input_reader: ReaderId<InputEvent<T>>,
+	state: ImguiStatePtr,
+	capture_policy: ImguiCapturePolicy<T>,
+	gamepad: Option<(ImguiGamepad, ReaderId<InputEvent<T>>)>,
+	input_reader: Option<ReaderId<InputEvent<T>>>,
 	winit_reader: ReaderId<Event>,
+	ime_reader: ReaderId<ImguiImeEvent>,
+	touch: Option<ImguiTouch>,
+	routing: ImguiInputRouting,
+	/// Whether the last run fed input into the context, see [ImguiInputRouting].
+	routed: bool,
 }
 impl<'s, T: BindingTypes> System<'s> for ImguiInputSystem<T> {
 	type SystemData = (
-		ReadExpect<'s, Arc<Mutex<ImguiState>>>,
+		Option<Read<'s, Window>>,
 		Read<'s, EventChannel<InputEvent<T>>>,
 		Read<'s, EventChannel<Event>>,
 		Write<'s, EventChannel<FilteredInputEvent<T>>>,
+		Read<'s, InputHandler<T>>,
+		Write<'s, ImguiCaptureState>,
+		Write<'s, FilteredInputHandler<T>>,
+		Read<'s, EventChannel<ImguiImeEvent>>,
+		Read<'s, ImguiInputFocus>,
 	);
 
-	fn run(&mut self, (state_mutex, input_events, winit_events, mut filtered_events): Self::SystemData) {
-		let state = &mut state_mutex.lock().unwrap();
-		let context = &mut state.context;
-
-		for event in winit_events.read(&mut self.winit_reader) {
-			match event {
-				Event::WindowEvent {
-					event: WindowEvent::Resized(size),
-					..
-				} => {
-					context.io_mut().display_size = [size.width as f32, size.height as f32];
-				},
-				_ => {},
+	fn run(
+		&mut self,
+		(
+			window,
+			input_events,
+			winit_events,
+			mut filtered_events,
+			input_handler,
+			mut capture,
+			mut filtered_handler,
+			ime_events,
+			focus,
+		): Self::SystemData,
+	) {
+		let _access = lock_imgui();
+
+		let filters_input = self.input_reader.is_some();
+		let hidpi = window.as_ref().map_or(1.0, |window| window.get_hidpi_factor() as f32);
+		if filters_input {
+			filtered_handler.begin_frame(&input_handler.bindings);
+		}
+
+		{
+			let state = &mut *self.state.lock().unwrap();
+
+			let routed = self.routing.receives(state.id(), &focus);
+			if self.routed && !routed {
+				release_input(state.io_mut());
+			}
+			self.routed = routed;
+			state.receives_input = routed;
+
+			if let Some(touch) = self.touch.as_mut().filter(|_| routed) {
+				touch.begin_frame(state.io_mut());
+			}
+
+			for event in winit_events.read(&mut self.winit_reader) {
+				// Masked with the capture state of the last frame, which is what imgui acted on.
+				if filters_input {
+					filtered_handler.send_event(event, &capture, hidpi);
+				}
+
+				if !routed && !is_window_geometry(event) {
+					continue;
+				}
+
+				match event {
+					Event::WindowEvent { event, .. } if is_text_input(event) => handle_text_input(state.io_mut(), event),
+					Event::WindowEvent {
+						event: WindowEvent::Touch(touch),
+						..
+					} => {
+						if let Some(emulation) = &mut self.touch {
+							let scale = hidpi / state.io().display_framebuffer_scale[0];
+							emulation.handle_event(state.io_mut(), touch, scale);
+						}
+					},
+					// `WinitPlatform` updates the display size and framebuffer scale on `Resized` and `HiDpiFactorChanged`.
+					_ => {
+						if let Some(window) = &window {
+							let (platform, io) = state.platform_and_io();
+							platform.handle_event(io, window, event);
+						}
+					},
+				}
+			}
+
+			for event in ime_events.read(&mut self.ime_reader).filter(|_| routed) {
+				state.handle_ime_event(event);
+			}
+
+			if let Some(touch) = self.touch.as_mut().filter(|_| routed) {
+				touch.end_frame(state.io_mut());
+			}
+
+			if let Some((gamepad, reader)) = &mut self.gamepad {
+				for event in input_events.read(reader).filter(|_| routed) {
+					gamepad.handle_event(event);
+				}
+				if routed {
+					gamepad.update_nav_inputs(state.io_mut());
+				}
 			}
 		}
-		for input in input_events.read(&mut self.input_reader) {
-			match input {
-				InputEvent::MouseMoved { .. } |
-				InputEvent::MouseButtonPressed(_) |
-				InputEvent::MouseButtonReleased(_) |
-				InputEvent::MouseWheelMoved(_) => {
-					if !context.io().want_capture_mouse {
-						filtered_events.single_write(FilteredInputEvent(input.clone()));
-					}
-				},
-				InputEvent::KeyPressed { .. } | InputEvent::KeyReleased { .. } => {
-					if !context.io().want_capture_keyboard {
-						filtered_events.single_write(FilteredInputEvent(input.clone()));
-					}
-				},
-				_ => filtered_events.single_write(FilteredInputEvent(input.clone())),
+
+		let input_reader = match &mut self.input_reader {
+			Some(input_reader) => input_reader,
+			None => return,
+		};
+		*capture = ImguiCaptureState {
+			mouse: context::any_wants_capture(|io| io.want_capture_mouse),
+			keyboard: context::any_wants_capture(|io| io.want_capture_keyboard),
+			text_input: context::any_wants_capture(|io| io.want_text_input),
+		};
+		for input in input_events.read(input_reader) {
+			let filtered = self.capture_policy.is_filtered(input, &capture, &input_handler.bindings);
+			filtered_handler.send_input_event(input, filtered);
+			if !filtered {
+				filtered_events.single_write(FilteredInputEvent(input.clone()));
 			}
 		}
 	}
 }
 
+/// Forwards keyboard and text events into imgui in the same frame they arrive.
+///
+/// These events are not passed on to `WinitPlatform`, so characters are never duplicated.
+fn handle_text_input(io: &mut imgui::Io, event: &WindowEvent) {
+	match *event {
+		// Backspace is reported as both a key and a '\u{7f}' character; only the key should reach imgui.
+		WindowEvent::ReceivedCharacter(c) if c != '\u{7f}' => io.add_input_character(c),
+		WindowEvent::KeyboardInput {
+			input: KeyboardInput {
+				state,
+				virtual_keycode: Some(key),
+				..
+			},
+			..
+		} => {
+			io.keys_down[key as usize] = state == ElementState::Pressed;
+			// `KeyboardInput::modifiers` holds the state from before the event on X11, so modifiers are tracked from
+			// their own keys like `WinitPlatform` does.
+			let down = |left: VirtualKeyCode, right: VirtualKeyCode| io.keys_down[left as usize] || io.keys_down[right as usize];
+			let (shift, ctrl, alt, logo) = (
+				down(VirtualKeyCode::LShift, VirtualKeyCode::RShift),
+				down(VirtualKeyCode::LControl, VirtualKeyCode::RControl),
+				down(VirtualKeyCode::LAlt, VirtualKeyCode::RAlt),
+				down(VirtualKeyCode::LWin, VirtualKeyCode::RWin),
+			);
+			io.key_shift = shift;
+			io.key_ctrl = ctrl;
+			io.key_alt = alt;
+			io.key_super = logo;
+		},
+		_ => {},
+	}
+}
+
+/// Lets go of everything held when a context stops receiving input, so nothing stays pressed until it gets input again.
+fn release_input(io: &mut imgui::Io) {
+	io.keys_down.iter_mut().for_each(|down| *down = false);
+	io.mouse_down.iter_mut().for_each(|down| *down = false);
+	io.nav_inputs.iter_mut().for_each(|input| *input = 0.0);
+	io.key_shift = false;
+	io.key_ctrl = false;
+	io.key_alt = false;
+	io.key_super = false;
+	io.mouse_pos = [-std::f32::MAX, -std::f32::MAX];
+}
+
+/// Returns true for the events every context receives regardless of its [ImguiInputRouting].
+fn is_window_geometry(event: &Event) -> bool {
+	match event {
+		Event::WindowEvent {
+			event: WindowEvent::Resized(_),
+			..
+		} |
+		Event::WindowEvent {
+			event: WindowEvent::HiDpiFactorChanged(_),
+			..
+		} => true,
+		_ => false,
+	}
+}
+
+/// Returns true for window events which are consumed by [handle_text_input].
+fn is_text_input(event: &WindowEvent) -> bool {
+	match event {
+		WindowEvent::ReceivedCharacter(_) | WindowEvent::KeyboardInput { .. } => true,
+		_ => false,
+	}
+}
+
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""), Clone(bound = ""))]
 pub struct ImguiInputSystemDesc<T: BindingTypes> {
 	_marker: std::marker::PhantomData<T>,
 	config_flags: imgui::ConfigFlags,
+	fonts: ImguiFontsConfig,
+	hidpi_mode: HiDpiMode,
+	context_id: ImguiContextId,
+	offscreen: Option<[u32; 2]>,
+	/// `None` until set with `with_ini_path`, the default is derived from the context id.
+	ini_path: Option<Option<PathBuf>>,
+	ini_settings: Option<String>,
+	style: Option<ImguiStyle>,
+	capture_policy: ImguiCapturePolicy<T>,
+	gamepad: Option<ImguiGamepadConfig>,
+	cursor_mode: ImguiCursorMode,
+	touch: Option<ImguiTouchConfig>,
+	routing: ImguiInputRouting,
+	#[derivative(Debug = "ignore")]
+	clipboard: Option<ImguiClipboardPtr>,
 }
+impl<T: BindingTypes> Default for ImguiInputSystemDesc<T> {
+	fn default() -> Self { Self::new(default_config_flags()) }
+}
+
 impl<T: BindingTypes> ImguiInputSystemDesc<T> {
 	pub fn new(config_flags: imgui::ConfigFlags) -> Self {
 		Self {
 			_marker: Default::default(),
 			config_flags,
+			fonts: ImguiFontsConfig::default(),
+			hidpi_mode: HiDpiMode::Default,
+			context_id: ImguiContextId::DEFAULT,
+			offscreen: None,
+			ini_path: None,
+			ini_settings: None,
+			style: None,
+			capture_policy: ImguiCapturePolicy::default(),
+			gamepad: None,
+			cursor_mode: ImguiCursorMode::default(),
+			touch: None,
+			routing: ImguiInputRouting::default(),
+			clipboard: None,
 		}
 	}
+
+	/// Which window events this context receives, see [ImguiInputRouting].
+	pub fn with_input_routing(mut self, routing: ImguiInputRouting) -> Self {
+		self.routing = routing;
+		self
+	}
+
+	/// Navigates imgui with controllers, enabling `ConfigFlags::NAV_ENABLE_GAMEPAD`.
+	pub fn with_gamepad(mut self, gamepad: ImguiGamepadConfig) -> Self {
+		self.gamepad = Some(gamepad);
+		self
+	}
+
+	/// Clipboard used by imgui's text widgets, see [ImguiClipboard].
+	pub fn with_clipboard(mut self, clipboard: impl ImguiClipboard) -> Self {
+		self.clipboard = Some(Arc::new(Mutex::new(clipboard)));
+		self
+	}
+
+	/// Who sets the window's mouse cursor, see [ImguiCursorMode].
+	pub fn with_cursor_mode(mut self, cursor_mode: ImguiCursorMode) -> Self {
+		self.cursor_mode = cursor_mode;
+		self
+	}
+
+	/// Emulates the mouse with touch input, see [ImguiTouchConfig].
+	pub fn with_touch(mut self, touch: ImguiTouchConfig) -> Self {
+		self.touch = Some(touch);
+		self
+	}
+
+	/// Style applied when the context is created, see also `ImguiState::set_style_asset`.
+	pub fn with_style(mut self, style: ImguiStyle) -> Self {
+		self.style = Some(style);
+		self
+	}
+
+	/// Decides which input events are left out of [FilteredInputEvent]s while imgui captures input.
+	pub fn with_capture_policy(mut self, capture_policy: ImguiCapturePolicy<T>) -> Self {
+		self.capture_policy = capture_policy;
+		self
+	}
+
+	/// File the window layout is loaded from and saved to, relative to the application root unless absolute. `None`
+	/// disables saving. Defaults to `imgui.ini` for the default context and `imgui-<id>.ini` for others.
+	pub fn with_ini_path(mut self, ini_path: Option<PathBuf>) -> Self {
+		self.ini_path = Some(ini_path);
+		self
+	}
+
+	/// Settings loaded in place of the ini file, e.g. from [ImguiState::save_ini_settings].
+	pub fn with_ini_settings(mut self, ini_settings: String) -> Self {
+		self.ini_settings = Some(ini_settings);
+		self
+	}
+
+	/// Creates the context `context_id` instead of the default one, so several plugins can each render an independent
+	/// UI, e.g. to a different target. UI code draws into it with [with_context].
+	pub fn with_context_id(mut self, context_id: ImguiContextId) -> Self {
+		self.context_id = context_id;
+		self
+	}
+
+	/// Fixes the display size to `size`, taking the cursor from [ImguiOffscreen] instead of the window.
+	pub fn with_offscreen(mut self, size: [u32; 2]) -> Self {
+		self.offscreen = Some(size);
+		self
+	}
+
+	/// How the window's DPI factor is applied to imgui; fonts are rasterized at the resulting framebuffer scale.
+	pub fn with_hidpi_mode(mut self, hidpi_mode: HiDpiMode) -> Self {
+		self.hidpi_mode = hidpi_mode;
+		self
+	}
+
+	/// Fonts added to the imgui font atlas, in place of the embedded imgui font.
+	pub fn with_fonts(mut self, fonts: ImguiFontsConfig) -> Self {
+		self.fonts = fonts;
+		self
+	}
 }
 
 impl<'a, 'b, T: BindingTypes> SystemDesc<'a, 'b, ImguiInputSystem<T>> for ImguiInputSystemDesc<T> {
 	fn build(self, world: &mut World) -> ImguiInputSystem<T> {
 		<ImguiInputSystem<T> as System<'_>>::SystemData::setup(world);
 
-		let input_reader = Write::<EventChannel<InputEvent<T>>>::fetch(world).register_reader();
+		// Only the first context filters input, so every event is passed on once.
+		let input_reader = if world.has_value::<FilteredInputOwner>() {
+			None
+		} else {
+			world.insert(FilteredInputOwner);
+			Some(Write::<EventChannel<InputEvent<T>>>::fetch(world).register_reader())
+		};
 		let winit_reader = Write::<EventChannel<Event>>::fetch(world).register_reader();
+		let ime_reader = Write::<EventChannel<ImguiImeEvent>>::fetch(world).register_reader();
+		let gamepad = self.gamepad.map(|config| {
+			let reader = Write::<EventChannel<InputEvent<T>>>::fetch(world).register_reader();
+			(ImguiGamepad::new(config), reader)
+		});
 
-		// Setup Imgui
-		let mut context = imgui::Context::create();
+		let _access = lock_imgui();
 
-		context.fonts().add_font(&[imgui::FontSource::DefaultFontData {
-			config: Some(imgui::FontConfig {
-				size_pixels: 13.,
-				..imgui::FontConfig::default()
-			}),
-		}]);
+		// imgui-rs refuses to create a context while another one is current.
+		unsafe {
+			imgui::sys::igSetCurrentContext(std::ptr::null_mut());
+		}
+		let mut context = imgui::Context::create();
 
 		context.io_mut().config_flags |= self.config_flags;
+		context.io_mut().ime_set_input_screen_pos_fn = Some(ime::set_input_screen_pos);
+		match self.cursor_mode {
+			ImguiCursorMode::Imgui => {},
+			ImguiCursorMode::Game => context.io_mut().config_flags |= imgui::ConfigFlags::NO_MOUSE_CURSOR_CHANGE,
+			ImguiCursorMode::Software => context.io_mut().mouse_draw_cursor = true,
+		}
+		if gamepad.is_some() {
+			context.io_mut().config_flags |= imgui::ConfigFlags::NAV_ENABLE_GAMEPAD;
+			context.io_mut().backend_flags |= imgui::BackendFlags::HAS_GAMEPAD;
+		}
+
+		let ini_path = self.ini_path.unwrap_or_else(|| Some(self.context_id.default_ini_path()));
+		let ini_path = ini_path.map(|path| match application_root_dir() {
+			Ok(root) => root.join(path),
+			Err(_) => path,
+		});
+		context.set_ini_filename(ini_path.map(|path| imgui::ImString::new(path.to_string_lossy())));
+		if let Some(ini_settings) = &self.ini_settings {
+			context.load_ini_settings(ini_settings);
+		}
+		if let Some(style) = &self.style {
+			style.apply(context.style_mut());
+		}
+		let clipboard = self.clipboard.unwrap_or_else(clipboard::default_clipboard);
+		context.set_clipboard_backend(Box::new(clipboard::ClipboardBackend(clipboard)));
 
 		let mut platform = WinitPlatform::init(&mut context);
-		platform.attach_window(context.io_mut(), &world.fetch::<Window>(), HiDpiMode::Default);
+		if let Some(window) = world.try_fetch::<Window>() {
+			platform.attach_window(context.io_mut(), &window, self.hidpi_mode);
+		}
+		// Applied before the fonts are first built, so they are rasterized at the offscreen scale right away.
+		let offscreen = self.offscreen.map(ImguiOffscreen::new);
+		if let Some(offscreen) = &offscreen {
+			offscreen.apply(context.io_mut());
+		}
 
-		world.insert(Arc::new(Mutex::new(ImguiState {
-			context,
-			textures: Vec::default(),
-		})));
-		world.insert(platform);
+		let mut state = ImguiState::new(self.context_id, context, platform, self.fonts).expect("Failed to build imgui fonts");
+		state.offscreen = offscreen;
+		state.cursor_mode = self.cursor_mode;
+		let state = Arc::new(Mutex::new(state));
+		context::register(self.context_id, &state);
+
+		if self.context_id == ImguiContextId::DEFAULT {
+			world.insert(state.lock().unwrap().font_handles().clone());
+			world.insert(state.clone());
+		}
 
 		ImguiInputSystem {
+			state,
+			capture_policy: self.capture_policy,
+			gamepad,
 			input_reader,
 			winit_reader,
+			ime_reader,
+			touch: self.touch.map(ImguiTouch::new),
+			routing: self.routing,
+			routed: true,
 		}
 	}
 }
 
-static mut CURRENT_UI: Option<imgui::Ui<'static>> = None;
+/// Marks that an [ImguiInputSystem] writing [FilteredInputEvent]s exists.
+struct FilteredInputOwner;
 
-pub fn with(f: impl FnOnce(&imgui::Ui)) {
-	unsafe {
-		if let Some(ui) = current_ui() {
-			(f)(ui);
-		}
+/// Adds the imgui input and frame systems without any rendering.
+///
+/// This is what [RenderImgui] sets up minus the render group, for running UI logic headless, e.g. in tests or with
+/// the `empty` backend. `ImguiState::draw_data` holds the output of each finished frame.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""), Default(bound = ""))]
+pub struct ImguiBundle<T: BindingTypes> {
+	input: ImguiInputSystemDesc<T>,
+}
+
+impl<T: BindingTypes> ImguiBundle<T> {
+	pub fn new(input: ImguiInputSystemDesc<T>) -> Self { Self { input } }
+}
+
+impl<'a, 'b, T: BindingTypes> SystemBundle<'a, 'b> for ImguiBundle<T> {
+	fn build(self, world: &mut World, dispatcher: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+		add_systems(self.input, world, dispatcher, &[]);
+		Ok(())
 	}
 }
 
-pub unsafe fn current_ui<'a>() -> Option<&'a imgui::Ui<'a>> { CURRENT_UI.as_ref() }
+/// Adds the asset processors, input system and frame system of the context configured by `input`.
+fn add_systems<'a, 'b, T: BindingTypes>(
+	input: ImguiInputSystemDesc<T>,
+	world: &mut World,
+	dispatcher: &mut DispatcherBuilder<'a, 'b>,
+	input_dependencies: &[&str],
+) {
+	let id = input.context_id;
+	dispatcher.add(Processor::<ImguiFontAsset>::new(), &id.system_name("imgui_font_processor"), &[]);
+	dispatcher.add(Processor::<ImguiStyle>::new(), &id.system_name("imgui_style_processor"), &[]);
+	dispatcher.add(input.build(world), &id.system_name("imgui_input_system"), input_dependencies);
+	dispatcher.add(
+		ImguiFrameSystemDesc::new(id).build(world),
+		&id.system_name("imgui_frame"),
+		&[&id.system_name("imgui_input_system")],
+	);
+}
 
 /// A [RenderPlugin] for rendering Imgui elements.
+///
+/// The context it creates is configured with an [ImguiInputSystemDesc], given to [RenderImgui::new] or changed with
+/// [RenderImgui::with_input].
 #[derive(Derivative)]
-#[derivative(Debug(bound = ""))]
+#[derivative(Debug(bound = ""), Default(bound = ""))]
 pub struct RenderImgui<T: BindingTypes> {
-	target: Target,
-	config_flags: imgui::ConfigFlags,
-	_marker: std::marker::PhantomData<T>,
+	/// `None` until set with `with_target`, the default depends on whether the context renders offscreen.
+	target: Option<Target>,
+	index_type: ImguiIndexType,
+	color_space: ImguiColorSpace,
+	input: ImguiInputSystemDesc<T>,
+	/// Created with the first render plan, so it survives the graph being rebuilt.
+	offscreen_texture: Option<Handle<Texture>>,
 }
-impl<T: BindingTypes> Default for RenderImgui<T> {
-	#[cfg(feature = "docking")]
-	fn default() -> Self {
+
+#[cfg(feature = "docking")]
+fn default_config_flags() -> imgui::ConfigFlags { imgui::ConfigFlags::ENABLE_DOCKING }
+
+#[cfg(not(feature = "docking"))]
+fn default_config_flags() -> imgui::ConfigFlags { imgui::ConfigFlags::empty() }
+
+impl<T: BindingTypes> RenderImgui<T> {
+	/// Renders the context configured by `input`.
+	pub fn new(input: ImguiInputSystemDesc<T>) -> Self {
 		Self {
-			target: Default::default(),
-			_marker: Default::default(),
-			config_flags: imgui::ConfigFlags::ENABLE_DOCKING,
+			input,
+			..Default::default()
 		}
 	}
 
-	#[cfg(not(feature = "docking"))]
-	fn default() -> Self {
-		Self {
-			target: Default::default(),
-			_marker: Default::default(),
-			config_flags: imgui::ConfigFlags::empty(),
-		}
+	/// Changes the configuration of the context, e.g. `.with_input(|input| input.with_fonts(fonts))`.
+	pub fn with_input(mut self, f: impl FnOnce(ImguiInputSystemDesc<T>) -> ImguiInputSystemDesc<T>) -> Self {
+		self.input = f(self.input);
+		self
 	}
-}
 
-impl<T: BindingTypes> RenderImgui<T> {
 	pub fn with_imgui_config(mut self, config_flags: imgui::ConfigFlags) -> Self {
-		self.config_flags = config_flags;
+		self.input.config_flags = config_flags;
 		self
 	}
 
 	/// Select render target on which UI should be rendered.
 	pub fn with_target(mut self, target: Target) -> Self {
-		self.target = target;
+		self.target = Some(target);
+		self
+	}
+
+	/// Type of the indices uploaded to the GPU, see [ImguiIndexType].
+	pub fn with_index_type(mut self, index_type: ImguiIndexType) -> Self {
+		self.index_type = index_type;
+		self
+	}
+
+	/// How colors are written to the render target, see [ImguiColorSpace]. The default suits the `Srgb` formats of
+	/// the window and offscreen images.
+	pub fn with_color_space(mut self, color_space: ImguiColorSpace) -> Self {
+		self.color_space = color_space;
+		self
+	}
+
+	/// Renders the UI into an offscreen color image of `size` pixels instead of the window.
+	///
+	/// The image is the first color output of the target selected with `with_target`, or else of the context's
+	/// [ImguiContextId::offscreen_target], e.g. [IMGUI_OFFSCREEN_TARGET] for the default context. It can be read by
+	/// render groups of other targets through `TargetImage::Color`, and is also copied into the texture
+	/// `ImguiOffscreen::texture` before the `Main` target is drawn, for use in materials. Pointer input is taken from
+	/// `ImguiState::offscreen`.
+	pub fn with_offscreen(mut self, size: [u32; 2]) -> Self {
+		self.input.offscreen = Some(size);
 		self
 	}
 }
 
 impl<B: Backend, T: BindingTypes> RenderPlugin<B> for RenderImgui<T> {
 	fn on_build<'a, 'b>(&mut self, world: &mut World, dispatcher: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
-		dispatcher.add(
-			ImguiInputSystemDesc::<T>::new(self.config_flags).build(world),
-			"imgui_input_system",
-			&["input_system", "window"],
-		);
+		if !world.has_value::<ImguiDrawCallbacks<B>>() {
+			world.insert(ImguiDrawCallbacks::<B>::default());
+		}
 
+		add_systems(self.input.clone(), world, dispatcher, &["input_system", "window"]);
 		Ok(())
 	}
 
-	fn on_plan(&mut self, plan: &mut RenderPlan<B>, _factory: &mut Factory<B>, _: &World) -> Result<(), Error> {
-		plan.extend_target(self.target, |ctx| {
-			ctx.add(RenderOrder::Overlay, DrawImguiDesc::new().builder())?;
+	fn on_plan(&mut self, plan: &mut RenderPlan<B>, _factory: &mut Factory<B>, world: &World) -> Result<(), Error> {
+		let context_id = self.input.context_id;
+		let target = match (self.target, self.input.offscreen) {
+			(Some(target), _) => target,
+			(None, Some(_)) => context_id.offscreen_target(),
+			(None, None) => Target::Main,
+		};
+		if let Some(size) = self.input.offscreen {
+			plan.add_root(target);
+			plan.define_pass(target, TargetPlanOutputs {
+				colors: vec![OutputColor::Image(ImageOptions {
+					kind: hal::image::Kind::D2(size[0], size[1], 1, 1),
+					levels: 1,
+					format: hal::format::Format::Rgba8Srgb,
+					clear: Some(hal::command::ClearValue::Color([0.0; 4].into())),
+				})],
+				depth: None,
+			})?;
+
+			let texture = self
+				.offscreen_texture
+				.get_or_insert_with(|| {
+					let data = vec![0; size[0] as usize * size[1] as usize * 4];
+					pass::upload_texture(world, size[0], size[1], data, hal::format::Format::Rgba8Srgb)
+				})
+				.clone();
+			if let Some(state) = crate::state(context_id) {
+				let _access = lock_imgui();
+				if let Some(offscreen) = &mut state.lock().unwrap().offscreen {
+					offscreen.texture = Some(texture.clone());
+				}
+			}
+
+			plan.extend_target(Target::Main, move |ctx| {
+				let image = ctx.get_image(TargetImage::Color(target, 0))?;
+				let copy = ctx.graph().add_node(ImguiOffscreenCopyDesc { texture, size }.builder().with_image(image));
+				ctx.add_dep(copy);
+				Ok(())
+			});
+		}
+
+		let desc = DrawImguiDesc::new()
+			.with_index_type(self.index_type)
+			.with_context_id(context_id)
+			.with_color_space(self.color_space);
+		plan.extend_target(target, move |ctx| {
+			ctx.add(RenderOrder::Overlay, desc.builder())?;
 			Ok(())
 		});
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use amethyst::winit::{DeviceId, ModifiersState};
+	use context::TestState;
+
+	fn key(io: &mut imgui::Io, key: VirtualKeyCode, state: ElementState) {
+		// Modifiers are left empty the way X11 reports them for the press of a modifier key.
+		let event = WindowEvent::KeyboardInput {
+			device_id: unsafe { DeviceId::dummy() },
+			input: KeyboardInput {
+				scancode: 0,
+				state,
+				virtual_keycode: Some(key),
+				modifiers: ModifiersState::default(),
+			},
+		};
+		assert!(is_text_input(&event));
+		handle_text_input(io, &event);
+	}
+
+	fn modifiers(io: &imgui::Io) -> [bool; 4] { [io.key_shift, io.key_ctrl, io.key_alt, io.key_super] }
+
+	#[test]
+	fn modifiers_follow_their_keys() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let io = test.state.io_mut();
+
+		for &(code, index) in &[
+			(VirtualKeyCode::LShift, 0),
+			(VirtualKeyCode::RControl, 1),
+			(VirtualKeyCode::LAlt, 2),
+			(VirtualKeyCode::RWin, 3),
+		] {
+			key(io, code, ElementState::Pressed);
+			let mut expected = [false; 4];
+			expected[index] = true;
+			assert_eq!(modifiers(io), expected);
+			key(io, code, ElementState::Released);
+			assert_eq!(modifiers(io), [false; 4]);
+		}
+	}
+
+	#[test]
+	fn ctrl_shortcuts() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let io = test.state.io_mut();
+
+		key(io, VirtualKeyCode::LControl, ElementState::Pressed);
+		for &code in &[VirtualKeyCode::C, VirtualKeyCode::V, VirtualKeyCode::A] {
+			key(io, code, ElementState::Pressed);
+			assert!(io.key_ctrl && io.keys_down[code as usize]);
+			key(io, code, ElementState::Released);
+			assert!(io.key_ctrl && !io.keys_down[code as usize]);
+		}
+
+		// Ctrl is let go before C, which must not leave it stuck.
+		key(io, VirtualKeyCode::C, ElementState::Pressed);
+		key(io, VirtualKeyCode::LControl, ElementState::Released);
+		assert!(!io.key_ctrl && io.keys_down[VirtualKeyCode::C as usize]);
+		key(io, VirtualKeyCode::C, ElementState::Released);
+		assert_eq!(modifiers(io), [false; 4]);
+	}
+
+	#[test]
+	fn chords() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let io = test.state.io_mut();
+
+		key(io, VirtualKeyCode::LControl, ElementState::Pressed);
+		key(io, VirtualKeyCode::LShift, ElementState::Pressed);
+		key(io, VirtualKeyCode::RControl, ElementState::Pressed);
+		assert_eq!(modifiers(io), [true, true, false, false]);
+
+		// Both sides were down, so releasing one keeps the modifier held.
+		key(io, VirtualKeyCode::LControl, ElementState::Released);
+		assert_eq!(modifiers(io), [true, true, false, false]);
+		key(io, VirtualKeyCode::LShift, ElementState::Released);
+		assert_eq!(modifiers(io), [false, true, false, false]);
+		key(io, VirtualKeyCode::RControl, ElementState::Released);
+		assert_eq!(modifiers(io), [false; 4]);
+	}
+
+	#[test]
+	fn routing() {
+		let (game, editor) = (ImguiContextId::DEFAULT, ImguiContextId("editor"));
+		let focus = ImguiInputFocus::default();
+		assert!(ImguiInputRouting::All.receives(editor, &focus));
+		assert!(ImguiInputRouting::Focused.receives(game, &focus));
+		assert!(!ImguiInputRouting::Focused.receives(editor, &focus));
+		assert!(ImguiInputRouting::Focused.receives(editor, &ImguiInputFocus(Some(editor))));
+		assert!(!ImguiInputRouting::None.receives(game, &focus));
+	}
+
+	#[test]
+	fn release_input_lets_go_of_keys_and_buttons() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let io = test.state.io_mut();
+
+		key(io, VirtualKeyCode::LControl, ElementState::Pressed);
+		key(io, VirtualKeyCode::A, ElementState::Pressed);
+		io.mouse_down[0] = true;
+		io.mouse_pos = [10.0, 10.0];
+		release_input(io);
+
+		assert_eq!(modifiers(io), [false; 4]);
+		assert!(!io.keys_down[VirtualKeyCode::A as usize] && !io.mouse_down[0]);
+		assert_eq!(io.mouse_pos, [-std::f32::MAX, -std::f32::MAX]);
+	}
+
+	#[test]
+	fn drop_ends_the_frame_on_its_own_context() {
+		let mut test = TestState::new(ImguiContextId("dropped"));
+		test.state.begin_frame().unwrap();
+		// Another context is current when the state is dropped, and must not have its frame ended.
+		let other = unsafe { imgui::sys::igCreateContext(std::ptr::null_mut()) };
+		unsafe {
+			imgui::sys::igSetCurrentContext(other);
+		}
+		drop(test);
+
+		let _access = lock_imgui();
+		unsafe {
+			imgui::sys::igSetCurrentContext(other);
+			assert_eq!(imgui::sys::igGetFrameCount(), 0);
+			imgui::sys::igDestroyContext(other);
+		}
+	}
+
+	#[test]
+	fn default_ini_paths() {
+		assert_eq!(ImguiContextId::DEFAULT.default_ini_path(), PathBuf::from("imgui.ini"));
+		assert_eq!(ImguiContextId("editor").default_ini_path(), PathBuf::from("imgui-editor.ini"));
+	}
+
+	/// Shows the window "Tool", moved to `position` if given, and returns where it ended up.
+	fn show_tool(state: &mut ImguiState, position: Option<[f32; 2]>) -> [f32; 2] {
+		let ui = state.begin_frame().unwrap();
+		let mut window = imgui::Window::new(imgui::im_str!("Tool")).size([200.0, 100.0], imgui::Condition::FirstUseEver);
+		if let Some(position) = position {
+			window = window.position(position, imgui::Condition::Always);
+		}
+		let mut shown_at = [0.0; 2];
+		window.build(ui, || shown_at = ui.window_pos());
+		state.end_frame().unwrap();
+		shown_at
+	}
+
+	#[test]
+	fn ini_settings_round_trip() {
+		let settings = {
+			let mut test = TestState::new(ImguiContextId::DEFAULT);
+			assert_eq!(show_tool(&mut test.state, Some([123.0, 45.0])), [123.0, 45.0]);
+			test.state.save_ini_settings()
+		};
+		assert!(settings.contains("[Window][Tool]"), "{}", settings);
+
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		test.state.load_ini_settings(&settings);
+		assert_eq!(show_tool(&mut test.state, None), [123.0, 45.0]);
+		assert_eq!(test.state.save_ini_settings(), settings);
+	}
+
+	#[test]
+	fn ini_settings_wait_for_the_frame_to_end() {
+		let settings = {
+			let mut test = TestState::new(ImguiContextId::DEFAULT);
+			show_tool(&mut test.state, Some([300.0, 200.0]));
+			test.state.save_ini_settings()
+		};
+
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		test.state.begin_frame().unwrap();
+		test.state.load_ini_settings(&settings);
+		test.state.end_frame().unwrap();
+		test.state.apply_context_updates().unwrap();
+		assert_eq!(show_tool(&mut test.state, None), [300.0, 200.0]);
+	}
+
+	#[test]
+	fn characters_skip_backspace() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let io = test.state.io_mut();
+
+		let queued = || unsafe { (*imgui::sys::igGetIO()).InputQueueCharacters.Size };
+		handle_text_input(io, &WindowEvent::ReceivedCharacter('\u{7f}'));
+		assert_eq!(queued(), 0);
+		handle_text_input(io, &WindowEvent::ReceivedCharacter('a'));
+		assert_eq!(queued(), 1);
+	}
+}
@@ -0,0 +1,382 @@
+use amethyst::{
+	assets::{AssetStorage, Handle},
+	core::math::{Point3, Vector3},
+	ecs::World,
+	renderer::{
+		bundle::Target,
+		rendy::{
+			command::{
+				CommandBuffer,
+				CommandPool,
+				ExecutableState,
+				Family,
+				Fence,
+				Graphics,
+				IndividualReset,
+				MultiShot,
+				PendingState,
+				PrimaryLevel,
+				Queue,
+				QueueType,
+				SimultaneousUse,
+				Submission,
+				Submit,
+			},
+			factory::Factory,
+			frame::Frames,
+			graph::{
+				gfx_acquire_barriers,
+				gfx_release_barriers,
+				GraphContext,
+				ImageAccess,
+				Node,
+				NodeBuffer,
+				NodeBuildError,
+				NodeDesc,
+				NodeImage,
+			},
+			hal::{
+				self,
+				image::{Access, Layout},
+				memory::{Barrier, Dependencies},
+				pso::PipelineStage,
+			},
+		},
+		types::Backend,
+		Texture,
+	},
+};
+use derivative::Derivative;
+
+/// Render target defined by [crate::RenderImgui::with_offscreen] for the default context, see
+/// [crate::ImguiContextId::offscreen_target].
+pub const IMGUI_OFFSCREEN_TARGET: Target = Target::Custom("imgui_offscreen");
+
+/// Set in `ImguiState::offscreen` when a context renders into an offscreen image instead of the window.
+///
+/// The display size is fixed to `size` at a framebuffer scale of 1, whatever the window's DPI, and the window's cursor
+/// position is ignored. Systems mapping a pointer onto the surface showing the image, e.g. by raycasting against an
+/// in-world screen mesh with [ImguiOffscreen::set_cursor_from_ray], set `cursor` to the texture coordinates it hits, or
+/// `None` when it misses.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImguiOffscreen {
+	pub size: [u32; 2],
+	pub cursor: Option<[f32; 2]>,
+	/// Copy of the image the UI is rendered into, updated every frame before the `Main` target is drawn, which
+	/// materials can use like any other texture. `None` until the render graph is built by `RenderImgui`.
+	pub texture: Option<Handle<Texture>>,
+}
+
+impl ImguiOffscreen {
+	pub fn new(size: [u32; 2]) -> Self {
+		Self {
+			size,
+			cursor: None,
+			texture: None,
+		}
+	}
+
+	/// Sets `cursor` to where a ray hits the mesh showing the image, or `None` if it misses, see [raycast_uv].
+	pub fn set_cursor_from_ray(
+		&mut self,
+		origin: Point3<f32>,
+		direction: Vector3<f32>,
+		triangles: impl IntoIterator<Item = ([Point3<f32>; 3], [[f32; 2]; 3])>,
+	) {
+		self.cursor = raycast_uv(origin, direction, triangles);
+	}
+
+	/// Converts texture coordinates of the image to imgui's coordinates.
+	pub fn ui_position(&self, uv: [f32; 2]) -> [f32; 2] { [uv[0] * self.size[0] as f32, uv[1] * self.size[1] as f32] }
+
+	/// Overrides what the window platform set up for the next frame.
+	pub(crate) fn apply(&self, io: &mut imgui::Io) {
+		io.display_size = [self.size[0] as f32, self.size[1] as f32];
+		io.display_framebuffer_scale = [1.0, 1.0];
+		io.mouse_pos = match self.cursor {
+			Some(uv) => self.ui_position(uv),
+			None => [-std::f32::MAX, -std::f32::MAX],
+		};
+	}
+}
+
+/// Texture coordinates where a ray first hits one of `triangles`, each given by its vertices and their texture
+/// coordinates, or `None` if it misses all of them.
+///
+/// The ray and the vertices have to be in the same space, e.g. a ray from `Camera::screen_ray` transformed by the
+/// inverse of the screen mesh's global transform, and the mesh's vertices. Triangles are hit from both sides.
+pub fn raycast_uv(
+	origin: Point3<f32>,
+	direction: Vector3<f32>,
+	triangles: impl IntoIterator<Item = ([Point3<f32>; 3], [[f32; 2]; 3])>,
+) -> Option<[f32; 2]> {
+	let mut closest: Option<(f32, [f32; 2])> = None;
+	for (vertices, uvs) in triangles {
+		// Möller-Trumbore, giving the distance along the ray and the barycentric coordinates of the hit.
+		let edges = (vertices[1] - vertices[0], vertices[2] - vertices[0]);
+		let p = direction.cross(&edges.1);
+		let det = edges.0.dot(&p);
+		if det.abs() < std::f32::EPSILON {
+			continue;
+		}
+
+		let offset = origin - vertices[0];
+		let u = offset.dot(&p) / det;
+		let q = offset.cross(&edges.0);
+		let v = direction.dot(&q) / det;
+		let t = edges.1.dot(&q) / det;
+		if u < 0.0 || v < 0.0 || u + v > 1.0 || t < 0.0 || closest.map_or(false, |(closest, _)| closest <= t) {
+			continue;
+		}
+
+		let w = 1.0 - u - v;
+		closest = Some((t, [
+			uvs[0][0] * w + uvs[1][0] * u + uvs[2][0] * v,
+			uvs[0][1] * w + uvs[1][1] * u + uvs[2][1] * v,
+		]));
+	}
+
+	closest.map(|(_, uv)| uv)
+}
+
+type RecordedCopy<B> = (
+	Submit<B, SimultaneousUse>,
+	CommandBuffer<B, QueueType, PendingState<ExecutableState<MultiShot<SimultaneousUse>>>, PrimaryLevel, IndividualReset>,
+);
+
+/// Render graph node copying the offscreen image into [ImguiOffscreen::texture].
+#[derive(Debug)]
+pub(crate) struct ImguiOffscreenCopyDesc {
+	pub texture: Handle<Texture>,
+	pub size: [u32; 2],
+}
+
+impl<B: Backend> NodeDesc<B, World> for ImguiOffscreenCopyDesc {
+	type Node = ImguiOffscreenCopy<B>;
+
+	fn images(&self) -> Vec<ImageAccess> {
+		vec![ImageAccess {
+			access: Access::TRANSFER_READ,
+			usage: hal::image::Usage::TRANSFER_SRC,
+			layout: Layout::TransferSrcOptimal,
+			stages: PipelineStage::TRANSFER,
+		}]
+	}
+
+	fn build<'a>(
+		self,
+		ctx: &GraphContext<B>,
+		factory: &mut Factory<B>,
+		family: &mut Family<B>,
+		_queue: usize,
+		_aux: &World,
+		_buffers: Vec<NodeBuffer>,
+		mut images: Vec<NodeImage>,
+	) -> Result<Self::Node, NodeBuildError> {
+		let mut pool = factory.create_command_pool(family).map_err(NodeBuildError::OutOfMemory)?;
+		let image = images.remove(0);
+		let idle = record_copy(ctx, &mut pool, &image, None, self.size);
+
+		Ok(ImguiOffscreenCopy {
+			texture: self.texture,
+			size: self.size,
+			image,
+			pool,
+			idle,
+			copy: None,
+		})
+	}
+}
+
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+pub(crate) struct ImguiOffscreenCopy<B: Backend> {
+	texture: Handle<Texture>,
+	size: [u32; 2],
+	#[derivative(Debug = "ignore")]
+	image: NodeImage,
+	#[derivative(Debug = "ignore")]
+	pool: CommandPool<B, QueueType, IndividualReset>,
+	/// Only the barriers of the offscreen image, submitted until the texture has been loaded.
+	#[derivative(Debug = "ignore")]
+	idle: RecordedCopy<B>,
+	#[derivative(Debug = "ignore")]
+	copy: Option<RecordedCopy<B>>,
+}
+
+impl<B: Backend> Node<B, World> for ImguiOffscreenCopy<B> {
+	// Runs on the queue family materials are drawn on, so the texture never changes owner.
+	type Capability = Graphics;
+
+	fn run<'a>(
+		&mut self,
+		ctx: &GraphContext<B>,
+		_factory: &Factory<B>,
+		queue: &mut Queue<B>,
+		aux: &World,
+		_frames: &Frames<B>,
+		waits: &[(&'a B::Semaphore, PipelineStage)],
+		signals: &[&'a B::Semaphore],
+		fence: Option<&mut Fence<B>>,
+	) {
+		if self.copy.is_none() {
+			let storage = aux.fetch::<AssetStorage<Texture>>();
+			if let Some(texture) = storage.get(&self.texture).and_then(B::unwrap_texture) {
+				self.copy = Some(record_copy(ctx, &mut self.pool, &self.image, Some(texture.image().raw()), self.size));
+			}
+		}
+
+		let (submit, _) = self.copy.as_ref().unwrap_or(&self.idle);
+		unsafe {
+			queue.submit(
+				Some(Submission::new().submits(Some(submit)).wait(waits.iter().cloned()).signal(signals.iter())),
+				fence,
+			);
+		}
+	}
+
+	unsafe fn dispose(mut self, factory: &mut Factory<B>, _aux: &World) {
+		let buffers = self.copy.into_iter().chain(Some(self.idle)).map(|(_, buffer)| buffer.mark_complete());
+		self.pool.free_buffers(buffers);
+		factory.destroy_command_pool(self.pool);
+	}
+}
+
+/// Records the graph's barriers of the offscreen image around a copy of it into `texture`, which is sampled by
+/// shaders in between.
+fn record_copy<B: Backend>(
+	ctx: &GraphContext<B>,
+	pool: &mut CommandPool<B, QueueType, IndividualReset>,
+	image: &NodeImage,
+	texture: Option<&B::Image>,
+	size: [u32; 2],
+) -> RecordedCopy<B> {
+	let color = hal::image::SubresourceRange {
+		aspects: hal::format::Aspects::COLOR,
+		levels: 0..1,
+		layers: 0..1,
+	};
+	let layers = hal::image::SubresourceLayers {
+		aspects: hal::format::Aspects::COLOR,
+		level: 0,
+		layers: 0..1,
+	};
+
+	let mut buffer = pool.allocate_buffers(1).remove(0).begin(MultiShot(SimultaneousUse), ());
+	let mut encoder = buffer.encoder();
+
+	let (mut stages, mut barriers) = gfx_acquire_barriers(ctx, None, Some(image));
+	stages.start |= PipelineStage::TRANSFER;
+	stages.end |= PipelineStage::TRANSFER;
+	if let Some(texture) = texture {
+		stages.start |= PipelineStage::FRAGMENT_SHADER;
+		barriers.push(Barrier::Image {
+			states: (Access::SHADER_READ, Layout::ShaderReadOnlyOptimal)..(Access::TRANSFER_WRITE, Layout::TransferDstOptimal),
+			target: texture,
+			families: None,
+			range: color.clone(),
+		});
+	}
+	unsafe {
+		encoder.pipeline_barrier(stages, Dependencies::empty(), barriers);
+	}
+
+	if let Some(texture) = texture {
+		let source = ctx.get_image(image.id).expect("offscreen image does not exist");
+		unsafe {
+			encoder.copy_image(
+				source.raw(),
+				image.layout,
+				texture,
+				Layout::TransferDstOptimal,
+				Some(hal::command::ImageCopy {
+					src_subresource: layers.clone(),
+					src_offset: hal::image::Offset::ZERO,
+					dst_subresource: layers,
+					dst_offset: hal::image::Offset::ZERO,
+					extent: hal::image::Extent {
+						width: size[0],
+						height: size[1],
+						depth: 1,
+					},
+				}),
+			);
+		}
+	}
+
+	let (mut stages, mut barriers) = gfx_release_barriers(ctx, None, Some(image));
+	stages.start |= PipelineStage::TRANSFER;
+	stages.end |= PipelineStage::TRANSFER;
+	if let Some(texture) = texture {
+		stages.end |= PipelineStage::FRAGMENT_SHADER;
+		barriers.push(Barrier::Image {
+			states: (Access::TRANSFER_WRITE, Layout::TransferDstOptimal)..(Access::SHADER_READ, Layout::ShaderReadOnlyOptimal),
+			target: texture,
+			families: None,
+			range: color,
+		});
+	}
+	unsafe {
+		encoder.pipeline_barrier(stages, Dependencies::empty(), barriers);
+	}
+
+	buffer.finish().submit()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A unit quad in the XY plane at z = 0, with (0, 0) at its top left like imgui.
+	fn quad() -> Vec<([Point3<f32>; 3], [[f32; 2]; 3])> {
+		let (tl, tr, bl, br) = (
+			Point3::new(0.0, 1.0, 0.0),
+			Point3::new(1.0, 1.0, 0.0),
+			Point3::new(0.0, 0.0, 0.0),
+			Point3::new(1.0, 0.0, 0.0),
+		);
+		vec![
+			([tl, bl, br], [[0.0, 0.0], [0.0, 1.0], [1.0, 1.0]]),
+			([tl, br, tr], [[0.0, 0.0], [1.0, 1.0], [1.0, 0.0]]),
+		]
+	}
+
+	fn assert_uv(uv: Option<[f32; 2]>, expected: [f32; 2]) {
+		let uv = uv.expect("ray missed");
+		assert!((uv[0] - expected[0]).abs() < 1e-5 && (uv[1] - expected[1]).abs() < 1e-5, "{:?} != {:?}", uv, expected);
+	}
+
+	#[test]
+	fn rays_hitting_the_quad() {
+		let towards = Vector3::new(0.0, 0.0, -1.0);
+		assert_uv(raycast_uv(Point3::new(0.5, 0.5, 1.0), towards, quad()), [0.5, 0.5]);
+		assert_uv(raycast_uv(Point3::new(0.25, 0.75, 1.0), towards, quad()), [0.25, 0.25]);
+		assert_uv(raycast_uv(Point3::new(0.9, 0.1, 1.0), towards, quad()), [0.9, 0.9]);
+		// From behind and at an angle.
+		assert_uv(raycast_uv(Point3::new(0.0, 0.5, -1.0), Vector3::new(0.5, 0.0, 1.0), quad()), [0.5, 0.5]);
+	}
+
+	#[test]
+	fn rays_missing_the_quad() {
+		assert_eq!(raycast_uv(Point3::new(1.5, 0.5, 1.0), Vector3::new(0.0, 0.0, -1.0), quad()), None);
+		// Pointing away from it, or parallel to it.
+		assert_eq!(raycast_uv(Point3::new(0.5, 0.5, 1.0), Vector3::new(0.0, 0.0, 1.0), quad()), None);
+		assert_eq!(raycast_uv(Point3::new(0.5, 0.5, 1.0), Vector3::new(1.0, 0.0, 0.0), quad()), None);
+	}
+
+	#[test]
+	fn closest_triangle_wins() {
+		let mut triangles = quad();
+		let far = [Point3::new(-1.0, -1.0, -1.0), Point3::new(3.0, -1.0, -1.0), Point3::new(-1.0, 3.0, -1.0)];
+		triangles.insert(0, (far, [[0.0, 0.0]; 3]));
+		assert_uv(raycast_uv(Point3::new(0.5, 0.5, 1.0), Vector3::new(0.0, 0.0, -1.0), triangles), [0.5, 0.5]);
+	}
+
+	#[test]
+	fn cursor_in_ui_coordinates() {
+		let mut offscreen = ImguiOffscreen::new([800, 600]);
+		offscreen.set_cursor_from_ray(Point3::new(0.25, 0.5, 1.0), Vector3::new(0.0, 0.0, -1.0), quad());
+		assert_uv(offscreen.cursor, [0.25, 0.5]);
+		assert_eq!(offscreen.ui_position(offscreen.cursor.unwrap()), [200.0, 300.0]);
+	}
+}
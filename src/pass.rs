@@ -5,7 +5,6 @@ use amethyst::{
 		math::{Vector2, Vector4},
 	},
 	renderer::{
-		batch::OrderedOneLevelBatch,
 		pipeline::{PipelineDescBuilder, PipelinesBuilder},
 		rendy::{
 			command::{QueueId, RenderPassEncoder},
@@ -23,7 +22,7 @@ use amethyst::{
 				image::{self, Anisotropic, Filter, PackedColor, SamplerInfo, WrapMode},
 				pso,
 			},
-			mesh::{AsAttribute, AsVertex, Color, TexCoord, VertexFormat},
+			mesh::{AsAttribute, AsVertex, TexCoord, VertexFormat},
 			shader::{Shader, SpirvShader},
 			texture::TextureBuilder,
 		},
@@ -32,22 +31,22 @@ use amethyst::{
 		util,
 		Texture,
 	},
-	shrev::{EventChannel, ReaderId},
-	window::Window,
-	winit::Event,
 };
 
 use derivative::Derivative;
-use imgui::{internal::RawWrapper, DrawCmd, DrawCmdParams};
-use std::{
-	borrow::Cow,
-	collections::HashMap,
-	sync::{Arc, Mutex},
+use std::borrow::Cow;
+
+use crate::{
+	lock_imgui,
+	FontAtlasTexture,
+	ImguiCallbackId,
+	ImguiContextId,
+	ImguiDrawCallbacks,
+	ImguiDrawCmd,
+	ImguiDrawSnapshots,
+	ImguiError,
 };
 
-use crate::ImguiState;
-use imgui_winit_support::WinitPlatform;
-
 #[cfg(feature = "shader-compiler")]
 use amethyst::renderer::rendy::shader::{PathBufShaderInfo, ShaderKind, SourceLanguage};
 
@@ -81,6 +80,32 @@ lazy_static::lazy_static! {
 	(*FRAGMENT_SRC).stage(),
 	"main",
   );
+
+  static ref FRAGMENT_LINEAR_SRC: SpirvShader = PathBufShaderInfo::new(
+	PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders/imgui_linear.frag")),
+	ShaderKind::Fragment,
+	SourceLanguage::GLSL,
+	"main",
+  ).precompile().unwrap();
+
+  static ref FRAGMENT_LINEAR: SpirvShader = SpirvShader::new(
+	(*FRAGMENT_LINEAR_SRC).spirv().unwrap().to_vec(),
+	(*FRAGMENT_LINEAR_SRC).stage(),
+	"main",
+  );
+
+  static ref FRAGMENT_GAMMA_SRC: SpirvShader = PathBufShaderInfo::new(
+	PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders/imgui_gamma.frag")),
+	ShaderKind::Fragment,
+	SourceLanguage::GLSL,
+	"main",
+  ).precompile().unwrap();
+
+  static ref FRAGMENT_GAMMA: SpirvShader = SpirvShader::new(
+	(*FRAGMENT_GAMMA_SRC).spirv().unwrap().to_vec(),
+	(*FRAGMENT_GAMMA_SRC).stage(),
+	"main",
+  );
 }
 
 #[cfg(not(feature = "shader-compiler"))]
@@ -96,12 +121,21 @@ lazy_static::lazy_static! {
 	pso::ShaderStageFlags::FRAGMENT,
 	"main",
   ).unwrap();
-}
 
-lazy_static::lazy_static! {
-  static ref TIME: std::sync::Mutex<std::time::Instant> = std::sync::Mutex::new(std::time::Instant::now());
+  static ref FRAGMENT_LINEAR: SpirvShader = SpirvShader::from_bytes(
+	include_bytes!("../compiled/imgui_linear.frag.spv"),
+	pso::ShaderStageFlags::FRAGMENT,
+	"main",
+  ).unwrap();
+
+  static ref FRAGMENT_GAMMA: SpirvShader = SpirvShader::from_bytes(
+	include_bytes!("../compiled/imgui_gamma.frag.spv"),
+	pso::ShaderStageFlags::FRAGMENT,
+	"main",
+  ).unwrap();
 }
 
+/// Packed RGBA8 color, normalized to `[0, 1]` by the vertex input.
 #[repr(transparent)]
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
 pub struct ImguiColor(pub u32);
@@ -112,7 +146,7 @@ where
 	fn from(from: T) -> Self { ImguiColor(from.into()) }
 }
 impl AsAttribute for ImguiColor {
-	const FORMAT: hal::format::Format = hal::format::Format::Rgba32Uint;
+	const FORMAT: hal::format::Format = hal::format::Format::Rgba8Unorm;
 	const NAME: &'static str = "color";
 }
 
@@ -120,15 +154,18 @@ impl AsAttribute for ImguiColor {
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
 pub struct ImguiPushConstant {
 	inner: Vector4<f32>,
+	/// Only read by the fragment shader of `ImguiColorSpace::ManualGamma`.
+	gamma: f32,
 }
 impl ImguiPushConstant {
 	pub fn new(scale_x: f32, scale_y: f32, trans_x: f32, trans_y: f32) -> Self {
 		Self {
 			inner: Vector4::new(scale_x, scale_y, trans_x, trans_y),
+			gamma: 1.0,
 		}
 	}
 
-	pub fn raw(&self) -> &[f32] { &self.inner.data }
+	pub fn raw(&self) -> [f32; 5] { [self.inner.x, self.inner.y, self.inner.z, self.inner.w, self.gamma] }
 
 	pub fn scale(&self) -> Vector2<f32> { Vector2::new(self.inner.x, self.inner.y) }
 
@@ -143,90 +180,150 @@ impl ImguiPushConstant {
 		self.inner.z = translation.x;
 		self.inner.w = translation.y;
 	}
+
+	pub fn set_gamma(&mut self, gamma: f32) { self.gamma = gamma; }
 }
 impl Default for ImguiPushConstant {
 	fn default() -> Self {
 		Self {
 			inner: Vector4::new(1.0, 1.0, 0.0, 0.0),
+			gamma: 1.0,
 		}
 	}
 }
 
-/// Vertex format with position and UV texture coordinate attributes.
+/// Vertex format with the layout of `imgui::DrawVert`, so draw lists are uploaded without converting them.
 #[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
-pub struct ImguiArgs {
+pub struct ImguiVertex {
 	/// Position of the vertex in 2D space.
 	pub position: TexCoord,
 	/// UV texture coordinates used by the vertex.
 	pub tex_coord: TexCoord,
-	pub color: Color,
+	pub color: ImguiColor,
 }
 
-impl AsVertex for ImguiArgs {
-	fn vertex() -> VertexFormat { VertexFormat::new((TexCoord::vertex(), TexCoord::vertex(), Color::vertex())) }
+// Fail to compile unless both have the same layout, since the array lengths have to match.
+const _: [(); std::mem::size_of::<imgui::DrawVert>()] = [(); std::mem::size_of::<ImguiVertex>()];
+const _: [(); std::mem::align_of::<imgui::DrawVert>()] = [(); std::mem::align_of::<ImguiVertex>()];
+const _: [(); std::mem::offset_of!(imgui::DrawVert, pos)] = [(); std::mem::offset_of!(ImguiVertex, position)];
+const _: [(); std::mem::offset_of!(imgui::DrawVert, uv)] = [(); std::mem::offset_of!(ImguiVertex, tex_coord)];
+const _: [(); std::mem::offset_of!(imgui::DrawVert, col)] = [(); std::mem::offset_of!(ImguiVertex, color)];
+
+impl ImguiVertex {
+	/// Views imgui's vertices as `ImguiVertex`, which is how draw lists are written to the vertex buffer.
+	pub fn from_draw_verts(vertices: &[imgui::DrawVert]) -> &[ImguiVertex] {
+		// SAFETY: Both types are `repr(C)` plain data with the same size, alignment and field offsets, checked above,
+		// and every bit pattern of `[f32; 2]` and `[u8; 4]` is valid as `TexCoord` and `ImguiColor`.
+		unsafe { std::slice::from_raw_parts(vertices.as_ptr() as *const ImguiVertex, vertices.len()) }
+	}
 }
 
-impl From<imgui::DrawVert> for ImguiArgs {
-	fn from(other: imgui::DrawVert) -> Self {
-		Self {
-			position: other.pos.into(),
-			tex_coord: other.uv.into(),
-			color: normalize(other.col).into(),
-		}
-	}
+impl AsVertex for ImguiVertex {
+	fn vertex() -> VertexFormat { VertexFormat::new((TexCoord::vertex(), TexCoord::vertex(), ImguiColor::vertex())) }
 }
 
-#[inline(always)]
-pub fn normalize(src: [u8; 4]) -> [f32; 4] {
-	[
-		f32::from(src[0]) / 255.0,
-		f32::from(src[1]) / 255.0,
-		f32::from(src[2]) / 255.0,
-		f32::from(src[3]) / 255.0,
-	]
+/// Type of the indices uploaded for imgui draw lists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImguiIndexType {
+	/// `imgui::DrawIdx` as imgui was built with, drawn with a vertex offset per command.
+	Native,
+	/// Indices widened to `u32` with the vertex offsets of each command added to them.
+	U32,
+}
+impl Default for ImguiIndexType {
+	fn default() -> Self { ImguiIndexType::Native }
+}
+
+/// How imgui's colors, which are sRGB, are written to the render target.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImguiColorSpace {
+	/// Colors are written unchanged, for `Unorm` targets which store exactly what is written to them.
+	Linear,
+	/// Colors are converted to linear, for `Srgb` targets which encode what is written to them.
+	SrgbFramebuffer,
+	/// Colors are raised to this power, for targets expecting some other encoding.
+	ManualGamma(f32),
+}
+impl Default for ImguiColorSpace {
+	fn default() -> Self { ImguiColorSpace::SrgbFramebuffer }
+}
+
+impl ImguiColorSpace {
+	fn fragment_shader(self) -> &'static SpirvShader {
+		match self {
+			ImguiColorSpace::Linear => &*FRAGMENT_LINEAR,
+			ImguiColorSpace::SrgbFramebuffer => &*FRAGMENT,
+			ImguiColorSpace::ManualGamma(_) => &*FRAGMENT_GAMMA,
+		}
+	}
 }
 
 /// Draw opaque sprites without lighting.
 #[derive(Clone, Debug, PartialEq, Derivative)]
 #[derivative(Default(bound = ""))]
-pub struct DrawImguiDesc;
+pub struct DrawImguiDesc {
+	index_type: ImguiIndexType,
+	context_id: ImguiContextId,
+	color_space: ImguiColorSpace,
+}
 
 impl DrawImguiDesc {
 	/// Create instance of `DrawImgui` render group
 	pub fn new() -> Self { Default::default() }
 
-	fn generate_upload_font_textures(&self, world: &World, mut fonts: imgui::FontAtlasRefMut) -> Handle<Texture> {
-		let tex = fonts.build_rgba32_texture();
-
-		let loader = world.fetch_mut::<Loader>();
-		let texture_storage = world.fetch_mut::<AssetStorage<Texture>>();
-		let data = tex.data.to_vec();
-
-		let texture_builder = TextureBuilder::new()
-			.with_data_width(tex.width)
-			.with_data_height(tex.height)
-			.with_kind(image::Kind::D2(tex.width, tex.height, 1, 1))
-			.with_view_kind(image::ViewKind::D2)
-			.with_sampler_info(SamplerInfo {
-				min_filter: Filter::Linear,
-				mag_filter: Filter::Linear,
-				mip_filter: Filter::Linear,
-				wrap_mode: (WrapMode::Clamp, WrapMode::Clamp, WrapMode::Clamp),
-				lod_bias: 0.0.into(),
-				lod_range: std::ops::Range {
-					start: 0.0.into(),
-					end: 1000.0.into(),
-				},
-				comparison: None,
-				border: PackedColor(0),
-				normalized: true,
-				anisotropic: Anisotropic::Off,
-			})
-			.with_raw_data(Cow::Owned(data), Format::Rgba8Unorm);
+	pub fn with_index_type(mut self, index_type: ImguiIndexType) -> Self {
+		self.index_type = index_type;
+		self
+	}
 
-		loader.load_from_data(TextureData(texture_builder), (), &texture_storage)
+	/// Draws the context `context_id` instead of the default one.
+	pub fn with_context_id(mut self, context_id: ImguiContextId) -> Self {
+		self.context_id = context_id;
+		self
 	}
+
+	/// Selects the fragment shader matching the format of the render target, see [ImguiColorSpace].
+	pub fn with_color_space(mut self, color_space: ImguiColorSpace) -> Self {
+		self.color_space = color_space;
+		self
+	}
+}
+
+fn generate_upload_font_textures(world: &World, tex: &FontAtlasTexture) -> Handle<Texture> {
+	upload_texture(world, tex.width, tex.height, tex.data.to_vec(), Format::Rgba8Unorm)
+}
+
+/// A single transparent pixel, drawn in place of textures which are not loaded or not registered.
+fn generate_placeholder_texture(world: &World) -> Handle<Texture> { upload_texture(world, 1, 1, vec![0; 4], Format::Rgba8Unorm) }
+
+pub(crate) fn upload_texture(world: &World, width: u32, height: u32, data: Vec<u8>, format: Format) -> Handle<Texture> {
+	let loader = world.fetch_mut::<Loader>();
+	let texture_storage = world.fetch_mut::<AssetStorage<Texture>>();
+
+	let texture_builder = TextureBuilder::new()
+		.with_data_width(width)
+		.with_data_height(height)
+		.with_kind(image::Kind::D2(width, height, 1, 1))
+		.with_view_kind(image::ViewKind::D2)
+		.with_sampler_info(SamplerInfo {
+			min_filter: Filter::Linear,
+			mag_filter: Filter::Linear,
+			mip_filter: Filter::Linear,
+			wrap_mode: (WrapMode::Clamp, WrapMode::Clamp, WrapMode::Clamp),
+			lod_bias: 0.0.into(),
+			lod_range: std::ops::Range {
+				start: 0.0.into(),
+				end: 1000.0.into(),
+			},
+			comparison: None,
+			border: PackedColor(0),
+			normalized: true,
+			anisotropic: Anisotropic::Off,
+		})
+		.with_raw_data(Cow::Owned(data), format);
+
+	loader.load_from_data(TextureData(texture_builder), (), &texture_storage)
 }
 
 impl<B: Backend> RenderGroupDesc<B, World> for DrawImguiDesc {
@@ -242,63 +339,104 @@ impl<B: Backend> RenderGroupDesc<B, World> for DrawImguiDesc {
 		_buffers: Vec<NodeBuffer>,
 		_images: Vec<NodeImage>,
 	) -> Result<Box<dyn RenderGroup<B, World>>, failure::Error> {
-		let (state_mutex, mut winit_events) = <(ReadExpect<'_, Arc<Mutex<ImguiState>>>, Write<'_, EventChannel<Event>>)>::fetch(world);
+		let state_ptr = crate::state(self.context_id).ok_or(ImguiError::Uninitialized)?;
+		let _access = lock_imgui();
+		let mut state = state_ptr.lock().unwrap();
 
-		let mut state = state_mutex.lock().unwrap();
-		let context = &mut state.context;
+		// Draw lists are no longer limited to 64k vertices when the renderer honors `vtx_offset`.
+		state.io_mut().backend_flags.insert(imgui::BackendFlags::RENDERER_HAS_VTX_OFFSET);
 
 		let textures = TextureSub::new(factory)?;
 		let vertex = DynamicVertexBuffer::new();
-		let index = DynamicIndexBuffer::new();
 
 		let (pipeline, pipeline_layout) =
-			build_imgui_pipeline(factory, subpass, framebuffer_width, framebuffer_height, vec![textures.raw_layout()])?;
-
-		//imgui.set_ini_filename(config.ini.as_ref().map(|i| imgui::ImString::new(i)));
-
-		let font_texture = self.generate_upload_font_textures(&world, context.fonts());
+			build_imgui_pipeline(factory, subpass, vec![textures.raw_layout()], self.color_space.fragment_shader())?;
+		let mut constant = ImguiPushConstant::default();
+		if let ImguiColorSpace::ManualGamma(gamma) = self.color_space {
+			constant.set_gamma(gamma);
+		}
 
-		unsafe { crate::CURRENT_UI = Some(std::mem::transmute(context.frame())) }
+		let font_texture = generate_upload_font_textures(&world, state.font_atlas());
+		let placeholder_texture = generate_placeholder_texture(&world);
 
 		Ok(Box::new(DrawImgui::<B> {
+			context_id: self.context_id,
 			pipeline,
 			pipeline_layout,
 			vertex,
-			index,
+			index: DynamicIndexBuffer::new(),
+			index32: DynamicIndexBuffer::new(),
+			index_type: self.index_type,
 			textures,
 			font_texture,
-			constant: ImguiPushConstant::default(),
+			font_generation: state.font_atlas().generation,
+			placeholder_texture,
+			texture_ids: Vec::new(),
+			textures_generation: None,
+			viewport: framebuffer_viewport([framebuffer_width as f32, framebuffer_height as f32]),
+			constant,
 			commands: Vec::new(),
-			batches: Default::default(),
-			reader_id: winit_events.register_reader(),
+			widened_indices: Vec::new(),
 		}))
 	}
 }
 
 #[derive(Debug)]
-struct DrawCmdOps {
-	vertex_range: std::ops::Range<u32>,
-	index_range: std::ops::Range<u32>,
-	scissor: hal::pso::Rect,
-	texture_id: TextureId,
+enum DrawCmdOps {
+	Draw {
+		vertex_offset: i32,
+		index_range: std::ops::Range<u32>,
+		scissor: hal::pso::Rect,
+		texture_id: TextureId,
+	},
+	ResetRenderState,
+	Callback {
+		id: ImguiCallbackId,
+		scissor: hal::pso::Rect,
+	},
 }
 
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct DrawImgui<B: Backend> {
+	context_id: ImguiContextId,
 	pipeline: B::GraphicsPipeline,
 	pipeline_layout: B::PipelineLayout,
-	vertex: DynamicVertexBuffer<B, ImguiArgs>,
-	index: DynamicIndexBuffer<B, u16>,
-	batches: OrderedOneLevelBatch<TextureId, ImguiArgs>,
+	vertex: DynamicVertexBuffer<B, ImguiVertex>,
+	index: DynamicIndexBuffer<B, imgui::DrawIdx>,
+	index32: DynamicIndexBuffer<B, u32>,
+	index_type: ImguiIndexType,
 	textures: TextureSub<B>,
 	commands: Vec<DrawCmdOps>,
+	/// Indices of the last frame for `ImguiIndexType::U32`, kept to reuse the allocation.
+	widened_indices: Vec<u32>,
 	constant: ImguiPushConstant,
-	reader_id: ReaderId<Event>,
 	font_texture: Handle<Texture>,
+	font_generation: u64,
+	placeholder_texture: Handle<Texture>,
+	/// `TextureSub` ids of the textures in `ImguiTextures`, with the handle id they were resolved from.
+	texture_ids: Vec<Option<(u32, TextureId)>>,
+	/// Generation of `ImguiTextures` which `texture_ids` is up to date with, `None` while some are still loading.
+	textures_generation: Option<u64>,
+	viewport: hal::pso::Viewport,
 }
 
-impl<B: Backend> DrawImgui<B> {}
+impl<B: Backend> DrawImgui<B> {
+	/// Binds the imgui pipeline, buffers and viewport, restoring the render state after a callback.
+	fn bind_render_state(&self, encoder: &mut RenderPassEncoder<'_, B>, index: usize) {
+		encoder.bind_graphics_pipeline(&self.pipeline);
+
+		self.vertex.bind(index, 0, 0, encoder);
+		match self.index_type {
+			ImguiIndexType::Native => self.index.bind(index, 0, encoder),
+			ImguiIndexType::U32 => self.index32.bind(index, 0, encoder),
+		};
+
+		unsafe {
+			encoder.set_viewports(0, &[self.viewport.clone()]);
+		}
+	}
+}
 
 impl<B: Backend> RenderGroup<B, World> for DrawImgui<B> {
 	#[allow(clippy::identity_conversion)]
@@ -307,178 +445,213 @@ impl<B: Backend> RenderGroup<B, World> for DrawImgui<B> {
 		factory: &Factory<B>,
 		_queue: QueueId,
 		index: usize,
-		_subpass: hal::pass::Subpass<'_, B>,
+		subpass: hal::pass::Subpass<'_, B>,
 		world: &World,
 	) -> PrepareResult {
-		let (window, mut platform, state_mutex, winit_events) = <(
-			ReadExpect<'_, Window>,
-			WriteExpect<'_, WinitPlatform>,
-			ReadExpect<'_, Arc<Mutex<ImguiState>>>,
-			Read<'_, EventChannel<Event>>,
-		)>::fetch(world);
-
-		let mut state = state_mutex.lock().unwrap();
+		// Only the owned snapshot of the last frame is read, so UI systems never wait on rendering.
+		let snapshot = match world.try_fetch::<ImguiDrawSnapshots>().and_then(|snapshots| snapshots.get(self.context_id)) {
+			Some(snapshot) => snapshot,
+			None => return PrepareResult::DrawRecord,
+		};
 
-		state.context.fonts().tex_id = imgui::TextureId::from(std::usize::MAX);
+		// Replacing the handle drops the previous font texture, which is freed once `TextureSub` stops using it.
+		if snapshot.font_atlas.generation != self.font_generation {
+			self.font_texture = generate_upload_font_textures(world, &snapshot.font_atlas);
+			self.font_generation = snapshot.font_atlas.generation;
+		}
 
-		let (font_texture_id, _) = match self
+		let font_texture_id = self
 			.textures
-			.insert(factory, world, &self.font_texture, hal::image::Layout::ShaderReadOnlyOptimal)
-		{
-			Some(v) => v,
-			None => {
+			.insert(factory, world, &self.font_texture, hal::image::Layout::ShaderReadOnlyOptimal);
+		let placeholder_id = self
+			.textures
+			.insert(factory, world, &self.placeholder_texture, hal::image::Layout::ShaderReadOnlyOptimal);
+		let (font_texture_id, placeholder_id) = match (font_texture_id, placeholder_id) {
+			(Some((font_texture_id, _)), Some((placeholder_id, _))) => (font_texture_id, placeholder_id),
+			_ => {
 				self.textures.maintain(factory, world);
 				return PrepareResult::DrawRecord;
 			},
 		};
 
-		let texture_map = state
-			.textures
-			.iter()
-			.filter_map(|texture| {
-				match self
-					.textures
-					.insert(factory, world, &texture, hal::image::Layout::ShaderReadOnlyOptimal)
-				{
-					Some((tex_id, _)) => Some((texture.id(), tex_id)),
-					None => None,
+		// Only resolve registered textures again when the registry changed or some of them were still loading.
+		if self.textures_generation != Some(snapshot.textures.generation()) {
+			let slots = snapshot.textures.slots();
+			self.texture_ids.resize(slots.len(), None);
+			self.texture_ids.truncate(slots.len());
+
+			let mut loaded = true;
+			for (slot, texture_id) in slots.iter().zip(self.texture_ids.iter_mut()) {
+				match slot {
+					Some(handle) if texture_id.map_or(true, |(handle_id, _)| handle_id != handle.id()) => {
+						*texture_id = self
+							.textures
+							.insert(factory, world, handle, hal::image::Layout::ShaderReadOnlyOptimal)
+							.map(|(id, _)| (handle.id(), id));
+						loaded &= texture_id.is_some();
+					},
+					Some(_) => (),
+					None => *texture_id = None,
 				}
-			})
-			.collect::<HashMap<_, _>>();
-
-		let context = &mut state.context;
-
-		self.constant
-			.set_scale(Vector2::new(2.0 / context.io().display_size[0], 2.0 / context.io().display_size[1]));
-		self.constant.set_translation(Vector2::new(-1.0, -1.0));
+			}
 
-		let last_frame = &mut TIME.lock().unwrap() as &mut std::time::Instant;
-		std::mem::replace(last_frame, context.io_mut().update_delta_time(*last_frame));
+			self.textures_generation = if loaded { Some(snapshot.textures.generation()) } else { None };
+		}
 
-		unsafe {
-			if let Some(ui) = crate::current_ui() {
-				platform.prepare_render(ui, &window);
-			}
+		let draw_data = &snapshot.draw_data;
+		let projection = match ImguiProjection::new(draw_data.display_pos, draw_data.display_size, draw_data.framebuffer_scale) {
+			Some(projection) => projection,
+			// Nothing is visible, e.g. while the window is minimized.
+			None => {
+				self.textures.maintain(factory, world);
+				return PrepareResult::DrawRecord;
+			},
+		};
+		self.constant.set_scale(Vector2::from(projection.scale));
+		self.constant.set_translation(Vector2::from(projection.translation));
+		// The viewport is dynamic state, so it follows the surface when the window is resized.
+		self.viewport = projection.viewport();
+
+		let vertex_count: usize = draw_data.draw_lists.iter().map(|l| l.vertices.len()).sum();
+		let index_count: usize = draw_data.draw_lists.iter().map(|l| l.indices.len()).sum();
+		self.widened_indices.clear();
+		if self.index_type == ImguiIndexType::U32 {
+			self.widened_indices.reserve(index_count);
 		}
 
-		{
-			let draw_data = unsafe {
-				crate::CURRENT_UI = None;
-				imgui::sys::igRender();
-				&*(imgui::sys::igGetDrawData() as *mut imgui::DrawData)
-			};
-
-			let mut vertices = Vec::with_capacity(draw_data.total_vtx_count as usize);
-			let mut indices = Vec::with_capacity(draw_data.total_idx_count as usize);
-
-			self.commands.reserve(draw_data.draw_lists().count() * 3);
-
-			let mut index_range = std::ops::Range::<u32> { start: 0, end: 0 };
-
-			for draw_list in draw_data.draw_lists() {
-				for draw_cmd in draw_list.commands() {
-					match draw_cmd {
-						DrawCmd::Elements {
-							count,
-							cmd_params: DrawCmdParams { clip_rect, texture_id, .. },
-							..
-						} => {
-							let clip_off = draw_data.display_pos;
-							let clip_scale = draw_data.framebuffer_scale;
-							let fb = [draw_data.display_size[0] * clip_scale[0], draw_data.display_size[1] * clip_scale[1]];
-
-							let mut scissor = [
-								(clip_rect[0] - clip_off[0]) * clip_scale[0],
-								(clip_rect[1] - clip_off[1]) * clip_scale[1],
-								(clip_rect[2] - clip_off[0]) * clip_scale[0],
-								(clip_rect[3] - clip_off[1]) * clip_scale[1],
-							];
-
-							if scissor[0] < fb[0] && scissor[1] < fb[1] && scissor[2] >= 0.0 && scissor[3] >= 0.0 {
-								scissor[0] = scissor[0].max(0.0);
-								scissor[1] = scissor[1].max(0.0);
-
-								index_range.start = index_range.end;
-								index_range.end += count as u32;
-
-								self.commands.push(DrawCmdOps {
-									vertex_range: std::ops::Range {
-										start: vertices.len() as u32,
-										end: (vertices.len() + draw_list.vtx_buffer().len()) as u32,
-									},
-									index_range: index_range.clone(),
-									scissor: hal::pso::Rect {
-										x: (scissor[0]) as i16,
-										y: (scissor[1]) as i16,
-										w: (scissor[2] - scissor[0]) as i16,
-										h: (scissor[3] - scissor[1]) as i16,
-									},
-									texture_id: {
-										if texture_id.id() == std::usize::MAX {
-											font_texture_id
-										} else if let Some(tex_id) = texture_map.get(&(texture_id.id() as u32)) {
-											*tex_id
-										} else {
-											panic!("Bad texture ID");
-										}
-									},
-								});
-							}
-						},
-						DrawCmd::ResetRenderState => (), // TODO
-						DrawCmd::RawCallback { callback, raw_cmd } => unsafe { callback(draw_list.raw(), raw_cmd) },
-					}
+		self.commands.reserve(draw_data.draw_lists.len() * 3);
+
+		// Offsets of the current draw list in the buffers shared by all of them.
+		let mut base_vertex = 0;
+		let mut base_index = 0;
+		for draw_list in &draw_data.draw_lists {
+			for draw_cmd in &draw_list.commands {
+				match *draw_cmd {
+					ImguiDrawCmd::Elements {
+						count,
+						clip_rect,
+						texture_id,
+						vtx_offset,
+						idx_offset,
+					} => {
+						if let Some(scissor) = projection.scissor(clip_rect) {
+							let (vertex_offset, index_range) = match self.index_type {
+								ImguiIndexType::Native => {
+									let start = (base_index + idx_offset) as u32;
+									((base_vertex + vtx_offset) as i32, start..start + count as u32)
+								},
+								ImguiIndexType::U32 => {
+									let start = self.widened_indices.len() as u32;
+									let offset = (base_vertex + vtx_offset) as u32;
+									self.widened_indices.extend(
+										draw_list.indices[idx_offset..idx_offset + count]
+											.iter()
+											.map(|index| u32::from(*index) + offset),
+									);
+									(0, start..start + count as u32)
+								},
+							};
+
+							self.commands.push(DrawCmdOps::Draw {
+								vertex_offset,
+								index_range,
+								scissor,
+								texture_id: if texture_id.id() == std::usize::MAX {
+									font_texture_id
+								} else {
+									self.texture_ids
+										.get(texture_id.id())
+										.and_then(|texture_id| texture_id.map(|(_, id)| id))
+										.unwrap_or(placeholder_id)
+								},
+							});
+						}
+					},
+					ImguiDrawCmd::ResetRenderState => self.commands.push(DrawCmdOps::ResetRenderState),
+					ImguiDrawCmd::Callback { id, clip_rect } => {
+						if let Some(scissor) = projection.scissor(clip_rect) {
+							self.commands.push(DrawCmdOps::Callback { id, scissor });
+						}
+					},
 				}
-
-				vertices.extend(draw_list.vtx_buffer().iter().map(|v| (*v).into()).collect::<Vec<ImguiArgs>>());
-				indices.extend(draw_list.idx_buffer().iter().map(|v| (*v).into()).collect::<Vec<u16>>());
 			}
 
-			self.vertex.write(factory, index, vertices.len() as u64, &[vertices.iter()]);
-			self.index.write(factory, index, indices.len() as u64, &[indices.iter()]);
-
-			self.textures.maintain(factory, world);
+			base_vertex += draw_list.vertices.len();
+			base_index += draw_list.indices.len();
 		}
 
-		for event in winit_events.read(&mut self.reader_id) {
-			platform.handle_event(context.io_mut(), &window, &event);
-		}
+		// Each draw list is copied straight from the snapshot into the mapped buffer.
+		let vertices = draw_data.draw_lists.iter().map(|l| ImguiVertex::from_draw_verts(&l.vertices));
+		self.vertex.write(factory, index, vertex_count as u64, vertices);
+		match self.index_type {
+			ImguiIndexType::Native => {
+				let indices = draw_data.draw_lists.iter().map(|l| &l.indices[..]);
+				self.index.write(factory, index, index_count as u64, indices)
+			},
+			ImguiIndexType::U32 => self.index32.write(factory, index, index_count as u64, &[self.widened_indices.iter()]),
+		};
 
-		platform.prepare_frame(context.io_mut(), &window).unwrap();
-		unsafe {
-			crate::CURRENT_UI = Some(std::mem::transmute(context.frame()));
+		self.textures.maintain(factory, world);
+
+		if let Some(mut callbacks) = world.try_fetch_mut::<ImguiDrawCallbacks<B>>() {
+			for command in &self.commands {
+				if let DrawCmdOps::Callback { id, .. } = command {
+					if let Some(callback) = callbacks.get_mut(*id) {
+						callback.prepare(factory, index, subpass, world);
+					}
+				}
+			}
 		}
 
 		PrepareResult::DrawRecord
 	}
 
-	fn draw_inline(&mut self, mut encoder: RenderPassEncoder<'_, B>, index: usize, _: hal::pass::Subpass<'_, B>, _: &World) {
+	fn draw_inline(&mut self, mut encoder: RenderPassEncoder<'_, B>, index: usize, _: hal::pass::Subpass<'_, B>, world: &World) {
+		// Buffers were not written for this frame if `prepare` had nothing to draw.
+		if self.commands.is_empty() {
+			return;
+		}
+
 		let layout = &self.pipeline_layout;
-		encoder.bind_graphics_pipeline(&self.pipeline);
+		self.bind_render_state(&mut encoder, index);
+
+		let mut callbacks = world.try_fetch_mut::<ImguiDrawCallbacks<B>>();
+
+		for command in &self.commands {
+			match command {
+				DrawCmdOps::Draw {
+					vertex_offset,
+					index_range,
+					scissor,
+					texture_id,
+				} => {
+					if self.textures.loaded(*texture_id) {
+						self.textures.bind(layout, 0, *texture_id, &mut encoder);
+					}
 
-		self.vertex.bind(index, 0, 0, &mut encoder);
-		self.index.bind(index, 0, &mut encoder);
+					unsafe {
+						encoder.set_scissors(0, &[*scissor]);
 
-		for draw in &self.commands {
-			if self.textures.loaded(draw.texture_id) {
-				self.textures.bind(layout, 0, draw.texture_id, &mut encoder);
-			}
+						encoder.push_constants(
+							layout,
+							pso::ShaderStageFlags::VERTEX | pso::ShaderStageFlags::FRAGMENT,
+							0,
+							hal::memory::cast_slice::<f32, u32>(&self.constant.raw()),
+						);
 
-			unsafe {
-				encoder.set_scissors(0, &[draw.scissor]);
-
-				encoder.push_constants(
-					layout,
-					pso::ShaderStageFlags::VERTEX,
-					0,
-					hal::memory::cast_slice::<f32, u32>(self.constant.raw()),
-				);
-
-				encoder.draw_indexed(
-					draw.index_range.clone(),
-					draw.vertex_range.start as i32,
-					std::ops::Range { start: 0, end: 1 },
-				);
+						encoder.draw_indexed(index_range.clone(), *vertex_offset, std::ops::Range { start: 0, end: 1 });
+					}
+				},
+				DrawCmdOps::ResetRenderState => self.bind_render_state(&mut encoder, index),
+				DrawCmdOps::Callback { id, scissor } => {
+					if let Some(callback) = callbacks.as_mut().and_then(|callbacks| callbacks.get_mut(*id)) {
+						unsafe {
+							encoder.set_scissors(0, &[*scissor]);
+						}
+						callback.draw(&mut encoder, index, *scissor, world);
+						self.bind_render_state(&mut encoder, index);
+					}
+				},
 			}
 		}
 
@@ -487,34 +660,104 @@ impl<B: Backend> RenderGroup<B, World> for DrawImgui<B> {
 
 	fn dispose(self: Box<Self>, factory: &mut Factory<B>, _aux: &World) {
 		unsafe {
-			crate::CURRENT_UI = None;
-
 			factory.device().destroy_graphics_pipeline(self.pipeline);
 			factory.device().destroy_pipeline_layout(self.pipeline_layout);
 		}
 	}
 }
 
+/// Maps imgui's display rectangle to clip space and to the framebuffer it is drawn into.
+///
+/// Vertices are in logical units, so the projection only depends on the display rectangle. Scissors are in
+/// framebuffer pixels, which is the logical size times the framebuffer scale.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ImguiProjection {
+	scale: [f32; 2],
+	translation: [f32; 2],
+	display_pos: [f32; 2],
+	framebuffer_scale: [f32; 2],
+	/// Size of the framebuffer in pixels.
+	framebuffer: [f32; 2],
+}
+
+impl ImguiProjection {
+	/// Returns `None` if the display or framebuffer is empty, as it is while the window is minimized.
+	fn new(display_pos: [f32; 2], display_size: [f32; 2], framebuffer_scale: [f32; 2]) -> Option<Self> {
+		let framebuffer = [display_size[0] * framebuffer_scale[0], display_size[1] * framebuffer_scale[1]];
+		// Written so NaN sizes are rejected as well.
+		if !(display_size[0] > 0.0 && display_size[1] > 0.0 && framebuffer[0] >= 1.0 && framebuffer[1] >= 1.0) {
+			return None;
+		}
+
+		let scale = [2.0 / display_size[0], 2.0 / display_size[1]];
+		Some(Self {
+			scale,
+			translation: [-1.0 - display_pos[0] * scale[0], -1.0 - display_pos[1] * scale[1]],
+			display_pos,
+			framebuffer_scale,
+			framebuffer,
+		})
+	}
+
+	fn viewport(&self) -> hal::pso::Viewport { framebuffer_viewport(self.framebuffer) }
+
+	/// Converts an imgui clip rectangle to a scissor in framebuffer pixels, or `None` if it lies outside the
+	/// framebuffer.
+	fn scissor(&self, clip_rect: [f32; 4]) -> Option<hal::pso::Rect> {
+		let (off, scale, fb) = (self.display_pos, self.framebuffer_scale, self.framebuffer);
+		let scissor = [
+			(clip_rect[0] - off[0]) * scale[0],
+			(clip_rect[1] - off[1]) * scale[1],
+			(clip_rect[2] - off[0]) * scale[0],
+			(clip_rect[3] - off[1]) * scale[1],
+		];
+
+		if scissor[0] < fb[0] && scissor[1] < fb[1] && scissor[2] >= 0.0 && scissor[3] >= 0.0 {
+			let (x, y) = (scissor[0].max(0.0), scissor[1].max(0.0));
+			Some(hal::pso::Rect {
+				x: x as i16,
+				y: y as i16,
+				w: (scissor[2].min(fb[0]) - x) as i16,
+				h: (scissor[3].min(fb[1]) - y) as i16,
+			})
+		} else {
+			None
+		}
+	}
+}
+
+fn framebuffer_viewport(size: [f32; 2]) -> hal::pso::Viewport {
+	hal::pso::Viewport {
+		rect: hal::pso::Rect {
+			x: 0,
+			y: 0,
+			w: size[0] as i16,
+			h: size[1] as i16,
+		},
+		depth: 0.0..1.0,
+	}
+}
+
 fn build_imgui_pipeline<B: Backend>(
 	factory: &Factory<B>,
 	subpass: hal::pass::Subpass<'_, B>,
-	framebuffer_width: u32,
-	framebuffer_height: u32,
 	layouts: Vec<&B::DescriptorSetLayout>,
+	fragment: &SpirvShader,
 ) -> Result<(B::GraphicsPipeline, B::PipelineLayout), failure::Error> {
+	// One range shared by both stages, so the fragment shader's gamma directly follows the vertex constants.
 	let pipeline_layout = unsafe {
 		factory
 			.device()
-			.create_pipeline_layout(layouts, &[(pso::ShaderStageFlags::VERTEX, 0..16)])
+			.create_pipeline_layout(layouts, &[(pso::ShaderStageFlags::VERTEX | pso::ShaderStageFlags::FRAGMENT, 0..20)])
 	}?;
 
 	let shader_vertex = unsafe { VERTEX.module(factory).unwrap() };
-	let shader_fragment = unsafe { FRAGMENT.module(factory).unwrap() };
+	let shader_fragment = unsafe { fragment.module(factory).unwrap() };
 
 	let pipes = PipelinesBuilder::new()
 		.with_pipeline(
 			PipelineDescBuilder::new()
-				.with_vertex_desc(&[(ImguiArgs::vertex(), pso::VertexInputRate::Vertex)])
+				.with_vertex_desc(&[(ImguiVertex::vertex(), pso::VertexInputRate::Vertex)])
 				.with_input_assembler(pso::InputAssemblerDesc::new(hal::Primitive::TriangleList))
 				.with_rasterizer(hal::pso::Rasterizer {
 					polygon_mode: hal::pso::PolygonMode::Fill,
@@ -528,15 +771,7 @@ fn build_imgui_pipeline<B: Backend>(
 				.with_layout(&pipeline_layout)
 				.with_subpass(subpass)
 				.with_baked_states(hal::pso::BakedStates {
-					viewport: Some(hal::pso::Viewport {
-						rect: hal::pso::Rect {
-							x: 0,
-							y: 0,
-							w: framebuffer_width as i16,
-							h: framebuffer_height as i16,
-						},
-						depth: 0.0..1.0,
-					}),
+					viewport: None,
 					scissor: None,
 					..Default::default()
 				})
@@ -563,3 +798,72 @@ fn build_imgui_pipeline<B: Backend>(
 		Ok(mut pipes) => Ok((pipes.remove(0), pipeline_layout)),
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use hal::pso::Rect;
+
+	/// Where a vertex at `position` ends up in clip space.
+	fn project(projection: &ImguiProjection, position: [f32; 2]) -> [f32; 2] {
+		[
+			position[0] * projection.scale[0] + projection.translation[0],
+			position[1] * projection.scale[1] + projection.translation[1],
+		]
+	}
+
+	fn assert_near(a: [f32; 2], b: [f32; 2]) { assert!((a[0] - b[0]).abs() < 1e-5 && (a[1] - b[1]).abs() < 1e-5, "{:?} != {:?}", a, b) }
+
+	#[test]
+	fn display_corners_map_to_clip_space() {
+		for &scale in &[1.0, 1.5, 2.0] {
+			for &pos in &[[0.0, 0.0], [100.0, 50.0]] {
+				let projection = ImguiProjection::new(pos, [800.0, 600.0], [scale, scale]).unwrap();
+				assert_near(project(&projection, pos), [-1.0, -1.0]);
+				assert_near(project(&projection, [pos[0] + 400.0, pos[1] + 300.0]), [0.0, 0.0]);
+				assert_near(project(&projection, [pos[0] + 800.0, pos[1] + 600.0]), [1.0, 1.0]);
+			}
+		}
+	}
+
+	#[test]
+	fn viewport_covers_framebuffer() {
+		for &(scale, w, h) in &[(1.0, 800, 600), (1.5, 1200, 900), (2.0, 1600, 1200)] {
+			let viewport = ImguiProjection::new([0.0, 0.0], [800.0, 600.0], [scale, scale]).unwrap().viewport();
+			assert_eq!(viewport.rect, Rect { x: 0, y: 0, w, h });
+		}
+	}
+
+	#[test]
+	fn scissors_are_in_framebuffer_pixels() {
+		let clip_rect = [10.0, 20.0, 110.0, 220.0];
+		for &(scale, expected) in &[
+			(1.0, Rect { x: 10, y: 20, w: 100, h: 200 }),
+			(1.5, Rect { x: 15, y: 30, w: 150, h: 300 }),
+			(2.0, Rect { x: 20, y: 40, w: 200, h: 400 }),
+		] {
+			let projection = ImguiProjection::new([0.0, 0.0], [800.0, 600.0], [scale, scale]).unwrap();
+			assert_eq!(projection.scissor(clip_rect), Some(expected));
+		}
+	}
+
+	#[test]
+	fn scissors_are_clamped_to_framebuffer() {
+		for &scale in &[1.0, 1.5, 2.0] {
+			let projection = ImguiProjection::new([100.0, 50.0], [800.0, 600.0], [scale, scale]).unwrap();
+			let (w, h) = ((800.0 * scale) as i16, (600.0 * scale) as i16);
+			assert_eq!(projection.scissor([0.0, 0.0, 2000.0, 2000.0]), Some(Rect { x: 0, y: 0, w, h }));
+			assert_eq!(projection.scissor([900.0, 0.0, 1000.0, 100.0]), None);
+			assert_eq!(projection.scissor([0.0, 0.0, 50.0, 40.0]), None);
+		}
+	}
+
+	#[test]
+	fn empty_display_is_not_drawn() {
+		assert_eq!(ImguiProjection::new([0.0, 0.0], [0.0, 0.0], [1.0, 1.0]), None);
+		assert_eq!(ImguiProjection::new([0.0, 0.0], [800.0, 0.0], [2.0, 2.0]), None);
+		assert_eq!(ImguiProjection::new([0.0, 0.0], [-1.0, -1.0], [1.0, 1.0]), None);
+		assert_eq!(ImguiProjection::new([0.0, 0.0], [800.0, 600.0], [0.0, 0.0]), None);
+		assert_eq!(ImguiProjection::new([0.0, 0.0], [std::f32::NAN, 600.0], [1.0, 1.0]), None);
+	}
+}
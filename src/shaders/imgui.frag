@@ -4,7 +4,15 @@ layout (location = 0) in vec2 inUV;
 layout (location = 1) in vec4 inColor;
 layout (location = 0) out vec4 outColor;
 
+// imgui's colors are sRGB, while an sRGB framebuffer expects linear values to encode.
+vec3 toLinear(vec3 srgb)
+{
+	vec3 low = srgb / 12.92;
+	vec3 high = pow((srgb + 0.055) / 1.055, vec3(2.4));
+	return mix(low, high, step(vec3(0.04045), srgb));
+}
+
 void main()
 {
-	outColor = pow(inColor * texture(fontSampler, inUV), vec4(2.2));
+	outColor = vec4(toLinear(inColor.rgb), inColor.a) * texture(fontSampler, inUV);
 }
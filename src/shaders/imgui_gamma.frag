@@ -0,0 +1,15 @@
+#version 450
+layout (set=0, binding = 0) uniform sampler2D fontSampler;
+layout (location = 0) in vec2 inUV;
+layout (location = 1) in vec4 inColor;
+layout (location = 0) out vec4 outColor;
+
+// Follows the vertex stage's scale and translation.
+layout (push_constant) uniform PushConstants {
+	layout (offset = 16) float gamma;
+} pushConstants;
+
+void main()
+{
+	outColor = vec4(pow(inColor.rgb, vec3(pushConstants.gamma)), inColor.a) * texture(fontSampler, inUV);
+}
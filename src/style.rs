@@ -0,0 +1,161 @@
+use amethyst::{
+	assets::{Asset, Handle},
+	ecs::VecStorage,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Built-in imgui color schemes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImguiStylePreset {
+	Dark,
+	Light,
+	Classic,
+}
+
+/// Serializable imgui style, loadable with amethyst's `RonFormat`.
+///
+/// Colors start from `preset` when it is set, or from the current colors otherwise; `colors` then overrides
+/// individual entries by their `imgui::StyleColor` name, e.g. `"WindowBg": (0.1, 0.1, 0.1, 1.0)`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImguiStyle {
+	pub preset: Option<ImguiStylePreset>,
+	pub colors: BTreeMap<String, [f32; 4]>,
+	pub alpha: f32,
+	pub window_padding: [f32; 2],
+	pub window_rounding: f32,
+	pub window_border_size: f32,
+	pub child_rounding: f32,
+	pub popup_rounding: f32,
+	pub frame_padding: [f32; 2],
+	pub frame_rounding: f32,
+	pub frame_border_size: f32,
+	pub item_spacing: [f32; 2],
+	pub item_inner_spacing: [f32; 2],
+	pub indent_spacing: f32,
+	pub scrollbar_size: f32,
+	pub scrollbar_rounding: f32,
+	pub grab_min_size: f32,
+	pub grab_rounding: f32,
+	pub tab_rounding: f32,
+}
+impl Default for ImguiStyle {
+	fn default() -> Self {
+		Self {
+			preset: Some(ImguiStylePreset::Dark),
+			colors: BTreeMap::new(),
+			alpha: 1.0,
+			window_padding: [8.0, 8.0],
+			window_rounding: 7.0,
+			window_border_size: 1.0,
+			child_rounding: 0.0,
+			popup_rounding: 0.0,
+			frame_padding: [4.0, 3.0],
+			frame_rounding: 0.0,
+			frame_border_size: 0.0,
+			item_spacing: [8.0, 4.0],
+			item_inner_spacing: [4.0, 4.0],
+			indent_spacing: 21.0,
+			scrollbar_size: 14.0,
+			scrollbar_rounding: 9.0,
+			grab_min_size: 10.0,
+			grab_rounding: 0.0,
+			tab_rounding: 4.0,
+		}
+	}
+}
+
+impl Asset for ImguiStyle {
+	type Data = Self;
+	type HandleStorage = VecStorage<Handle<Self>>;
+
+	const NAME: &'static str = "imgui::Style";
+}
+
+impl ImguiStyle {
+	pub fn preset(preset: ImguiStylePreset) -> Self {
+		Self {
+			preset: Some(preset),
+			..Default::default()
+		}
+	}
+
+	/// Captures every value of `style`, including all of its colors.
+	pub fn from_imgui(style: &imgui::Style) -> Self {
+		Self {
+			preset: None,
+			colors: imgui::StyleColor::VARIANTS
+				.iter()
+				.map(|color| (format!("{:?}", color), style[*color]))
+				.collect(),
+			alpha: style.alpha,
+			window_padding: style.window_padding,
+			window_rounding: style.window_rounding,
+			window_border_size: style.window_border_size,
+			child_rounding: style.child_rounding,
+			popup_rounding: style.popup_rounding,
+			frame_padding: style.frame_padding,
+			frame_rounding: style.frame_rounding,
+			frame_border_size: style.frame_border_size,
+			item_spacing: style.item_spacing,
+			item_inner_spacing: style.item_inner_spacing,
+			indent_spacing: style.indent_spacing,
+			scrollbar_size: style.scrollbar_size,
+			scrollbar_rounding: style.scrollbar_rounding,
+			grab_min_size: style.grab_min_size,
+			grab_rounding: style.grab_rounding,
+			tab_rounding: style.tab_rounding,
+		}
+	}
+
+	/// Writes this style into `style`. Color names which are not an `imgui::StyleColor` are ignored.
+	pub fn apply(&self, style: &mut imgui::Style) {
+		match self.preset {
+			Some(ImguiStylePreset::Dark) => style.use_dark_colors(),
+			Some(ImguiStylePreset::Light) => style.use_light_colors(),
+			Some(ImguiStylePreset::Classic) => style.use_classic_colors(),
+			None => style,
+		};
+		for color in imgui::StyleColor::VARIANTS.iter() {
+			if let Some(value) = self.colors.get(&format!("{:?}", color)) {
+				style[*color] = *value;
+			}
+		}
+
+		style.alpha = self.alpha;
+		style.window_padding = self.window_padding;
+		style.window_rounding = self.window_rounding;
+		style.window_border_size = self.window_border_size;
+		style.child_rounding = self.child_rounding;
+		style.popup_rounding = self.popup_rounding;
+		style.frame_padding = self.frame_padding;
+		style.frame_rounding = self.frame_rounding;
+		style.frame_border_size = self.frame_border_size;
+		style.item_spacing = self.item_spacing;
+		style.item_inner_spacing = self.item_inner_spacing;
+		style.indent_spacing = self.indent_spacing;
+		style.scrollbar_size = self.scrollbar_size;
+		style.scrollbar_rounding = self.scrollbar_rounding;
+		style.grab_min_size = self.grab_min_size;
+		style.grab_rounding = self.grab_rounding;
+		style.tab_rounding = self.tab_rounding;
+	}
+
+	pub fn to_ron(&self) -> Result<String, ron::ser::Error> { ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()) }
+}
+
+/// Shows imgui's style editor for the current style, with a button copying it to the clipboard as RON.
+///
+/// Returns the exported RON when the button was pressed.
+pub fn show_style_editor(ui: &imgui::Ui) -> Option<String> {
+	ui.show_default_style_editor();
+	ui.separator();
+	if !ui.button(imgui::im_str!("Export as RON"), [0.0, 0.0]) {
+		return None;
+	}
+
+	let ron = ImguiStyle::from_imgui(&ui.clone_style()).to_ron().ok()?;
+	ui.set_clipboard_text(&imgui::ImString::new(ron.clone()));
+	Some(ron)
+}
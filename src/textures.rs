@@ -0,0 +1,114 @@
+use amethyst::{assets::Handle, renderer::Texture};
+
+/// Maps textures drawn by imgui widgets, e.g. `imgui::Image`, to stable `imgui::TextureId`s.
+///
+/// Ids stay valid until the texture is removed, after which they may be reused. Textures which are not loaded yet,
+/// and ids which are not registered, are drawn with a transparent placeholder.
+#[derive(Clone, Debug, Default)]
+pub struct ImguiTextures {
+	slots: Vec<Option<Handle<Texture>>>,
+	generation: u64,
+}
+
+impl ImguiTextures {
+	/// Registers `handle`, returning the id it is already registered with if there is one.
+	pub fn insert(&mut self, handle: Handle<Texture>) -> imgui::TextureId {
+		if let Some(id) = self.id(&handle) {
+			return id;
+		}
+
+		self.generation += 1;
+		match self.slots.iter().position(Option::is_none) {
+			Some(free) => {
+				self.slots[free] = Some(handle);
+				imgui::TextureId::from(free)
+			},
+			None => {
+				self.slots.push(Some(handle));
+				imgui::TextureId::from(self.slots.len() - 1)
+			},
+		}
+	}
+
+	pub fn remove(&mut self, id: imgui::TextureId) -> Option<Handle<Texture>> {
+		let handle = self.slots.get_mut(id.id()).and_then(Option::take);
+		if handle.is_some() {
+			self.generation += 1;
+		}
+		handle
+	}
+
+	pub fn get(&self, id: imgui::TextureId) -> Option<&Handle<Texture>> { self.slots.get(id.id()).and_then(Option::as_ref) }
+
+	/// Returns the id `handle` is registered with.
+	pub fn id(&self, handle: &Handle<Texture>) -> Option<imgui::TextureId> {
+		self.slots
+			.iter()
+			.position(|slot| slot.as_ref() == Some(handle))
+			.map(imgui::TextureId::from)
+	}
+
+	pub(crate) fn slots(&self) -> &[Option<Handle<Texture>>] { &self.slots }
+
+	/// Changes every time a texture is inserted or removed.
+	pub(crate) fn generation(&self) -> u64 { self.generation }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{context::TestState, ImguiContextId};
+	use amethyst::assets::AssetStorage;
+
+	#[test]
+	fn removed_slots_are_reused() {
+		let storage = AssetStorage::<Texture>::new();
+		let (first, second, third) = (storage.allocate(), storage.allocate(), storage.allocate());
+		let mut textures = ImguiTextures::default();
+
+		let first_id = textures.insert(first.clone());
+		let second_id = textures.insert(second.clone());
+		assert_ne!(first_id, second_id);
+		assert_eq!(textures.insert(first.clone()), first_id);
+
+		assert_eq!(textures.remove(first_id), Some(first));
+		assert_eq!(textures.insert(third.clone()), first_id);
+		assert_eq!(textures.get(first_id), Some(&third));
+		assert_eq!(textures.get(second_id), Some(&second));
+	}
+
+	#[test]
+	fn stale_ids_resolve_to_nothing() {
+		let storage = AssetStorage::<Texture>::new();
+		let mut textures = ImguiTextures::default();
+
+		let id = textures.insert(storage.allocate());
+		textures.remove(id);
+		assert_eq!(textures.get(id), None);
+		assert_eq!(textures.remove(id), None);
+		assert_eq!(textures.get(imgui::TextureId::from(42)), None);
+	}
+
+	#[test]
+	fn frames_snapshot_the_registry_when_it_changes() {
+		let storage = AssetStorage::<Texture>::new();
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let generation = test.state.textures.generation();
+
+		let handle = storage.allocate();
+		let id = test.state.textures.insert(handle.clone());
+		assert_eq!(test.state.textures.generation(), generation + 1);
+		test.state.textures.insert(handle);
+		assert_eq!(test.state.textures.generation(), generation + 1);
+		test.state.begin_frame().unwrap();
+		test.state.end_frame().unwrap();
+		assert!(test.state.snapshot().textures.get(id).is_some());
+
+		test.state.textures.remove(id);
+		assert_eq!(test.state.textures.generation(), generation + 2);
+		assert!(test.state.snapshot().textures.get(id).is_some());
+		test.state.begin_frame().unwrap();
+		test.state.end_frame().unwrap();
+		assert!(test.state.snapshot().textures.get(id).is_none());
+	}
+}
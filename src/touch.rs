@@ -0,0 +1,321 @@
+use amethyst::winit::{Touch, TouchPhase};
+use std::{
+	collections::HashMap,
+	time::{Duration, Instant},
+};
+
+/// Emulates imgui's mouse with touch input, enabled with `ImguiInputSystemDesc::with_touch`.
+///
+/// A tap clicks, dragging with one finger holds the left button, dragging with two fingers scrolls and holding a
+/// finger still for `long_press` right clicks. Pens which report as touch behave the same.
+#[derive(Clone, Debug)]
+pub struct ImguiTouchConfig {
+	pub long_press: Duration,
+	/// Logical pixels a finger has to move before a touch becomes a drag.
+	pub drag_threshold: f32,
+	/// Logical pixels of two finger movement per wheel step.
+	pub scroll_step: f32,
+}
+impl Default for ImguiTouchConfig {
+	fn default() -> Self {
+		Self {
+			long_press: Duration::from_millis(500),
+			drag_threshold: 8.0,
+			scroll_step: 20.0,
+		}
+	}
+}
+
+impl ImguiTouchConfig {
+	pub fn with_long_press(mut self, long_press: Duration) -> Self {
+		self.long_press = long_press;
+		self
+	}
+
+	pub fn with_drag_threshold(mut self, drag_threshold: f32) -> Self {
+		self.drag_threshold = drag_threshold;
+		self
+	}
+
+	pub fn with_scroll_step(mut self, scroll_step: f32) -> Self {
+		self.scroll_step = scroll_step;
+		self
+	}
+}
+
+#[derive(Debug)]
+enum Gesture {
+	None,
+	/// A finger is down but has not moved or been held long enough to tell what it does.
+	Pending { start: [f32; 2], since: Instant },
+	/// The left button was pressed where the finger went down, in imgui's coordinates `cursor` moves to once imgui has
+	/// seen the press.
+	DragStarted { cursor: [f32; 2] },
+	Dragging,
+	LongPressed,
+	/// Lasts until every finger is lifted, so the remaining finger does not click.
+	Scrolling { center: [f32; 2] },
+}
+
+/// Touch state tracked from window events, translated into imgui mouse input.
+#[derive(Debug)]
+pub(crate) struct ImguiTouch {
+	config: ImguiTouchConfig,
+	/// Positions of the fingers currently down, in logical pixels.
+	touches: HashMap<u64, [f32; 2]>,
+	gesture: Gesture,
+	/// Buttons clicked by a gesture since the last `begin_frame`. They stay down through one imgui frame so the click
+	/// registers, and are released by the next `begin_frame`.
+	release: Vec<usize>,
+}
+
+impl ImguiTouch {
+	pub fn new(config: ImguiTouchConfig) -> Self {
+		Self {
+			config,
+			touches: HashMap::new(),
+			gesture: Gesture::None,
+			release: Vec::new(),
+		}
+	}
+
+	/// `scale` converts logical pixels to imgui's coordinates.
+	pub fn handle_event(&mut self, io: &mut imgui::Io, touch: &Touch, scale: f32) {
+		let position = [touch.location.x as f32, touch.location.y as f32];
+		match touch.phase {
+			TouchPhase::Started => {
+				self.touches.insert(touch.id, position);
+				match self.gesture {
+					Gesture::None => {
+						io.mouse_pos = [position[0] * scale, position[1] * scale];
+						self.gesture = Gesture::Pending {
+							start: position,
+							since: Instant::now(),
+						};
+					},
+					Gesture::Scrolling { .. } => {},
+					_ => {
+						io.mouse_down[0] = false;
+						self.gesture = Gesture::Scrolling { center: self.center() };
+					},
+				}
+			},
+			TouchPhase::Moved => {
+				self.touches.insert(touch.id, position);
+				match self.gesture {
+					Gesture::Pending { start, .. } if distance(start, position) > self.config.drag_threshold => {
+						// Press where the finger went down, the cursor follows it from the next `begin_frame` on.
+						io.mouse_pos = [start[0] * scale, start[1] * scale];
+						io.mouse_down[0] = true;
+						self.gesture = Gesture::DragStarted {
+							cursor: [position[0] * scale, position[1] * scale],
+						};
+					},
+					Gesture::DragStarted { .. } => {
+						self.gesture = Gesture::DragStarted {
+							cursor: [position[0] * scale, position[1] * scale],
+						}
+					},
+					Gesture::Dragging => io.mouse_pos = [position[0] * scale, position[1] * scale],
+					Gesture::Scrolling { center } => {
+						let new_center = self.center();
+						io.mouse_wheel_h += (new_center[0] - center[0]) / self.config.scroll_step;
+						io.mouse_wheel += (new_center[1] - center[1]) / self.config.scroll_step;
+						self.gesture = Gesture::Scrolling { center: new_center };
+					},
+					_ => {},
+				}
+			},
+			TouchPhase::Ended | TouchPhase::Cancelled => {
+				self.touches.remove(&touch.id);
+				match self.gesture {
+					Gesture::Pending { .. } if touch.phase == TouchPhase::Ended => self.click(io, 0),
+					// imgui has not seen the press yet, so it is released like a click.
+					Gesture::DragStarted { .. } => self.release.push(0),
+					Gesture::Dragging => io.mouse_down[0] = false,
+					Gesture::Scrolling { .. } if !self.touches.is_empty() => {
+						self.gesture = Gesture::Scrolling { center: self.center() };
+						return;
+					},
+					_ => {},
+				}
+				self.gesture = Gesture::None;
+			},
+		}
+	}
+
+	/// Releases the clicks imgui has seen during the last frame and moves the cursor of a drag it has seen begin.
+	/// Called once per frame before the events.
+	pub fn begin_frame(&mut self, io: &mut imgui::Io) {
+		for button in self.release.drain(..) {
+			io.mouse_down[button] = false;
+		}
+
+		if let Gesture::DragStarted { cursor } = self.gesture {
+			io.mouse_pos = cursor;
+			self.gesture = Gesture::Dragging;
+		}
+	}
+
+	/// Detects long presses. Called once per frame after the events.
+	pub fn end_frame(&mut self, io: &mut imgui::Io) {
+		if let Gesture::Pending { since, .. } = self.gesture {
+			if since.elapsed() >= self.config.long_press {
+				self.click(io, 1);
+				self.gesture = Gesture::LongPressed;
+			}
+		}
+	}
+
+	fn click(&mut self, io: &mut imgui::Io, button: usize) {
+		io.mouse_down[button] = true;
+		self.release.push(button);
+	}
+
+	fn center(&self) -> [f32; 2] {
+		let count = self.touches.len().max(1) as f32;
+		let sum = self.touches.values().fold([0.0, 0.0], |sum, position| [sum[0] + position[0], sum[1] + position[1]]);
+		[sum[0] / count, sum[1] / count]
+	}
+}
+
+fn distance(a: [f32; 2], b: [f32; 2]) -> f32 { ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)).sqrt() }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{context::TestState, ImguiContextId};
+	use amethyst::winit::{dpi::LogicalPosition, DeviceId};
+
+	fn touch(id: u64, phase: TouchPhase, x: f64, y: f64) -> Touch {
+		Touch {
+			device_id: unsafe { DeviceId::dummy() },
+			phase,
+			location: LogicalPosition::new(x, y),
+			id,
+		}
+	}
+
+	#[test]
+	fn taps_click_for_one_frame() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let io = test.state.io_mut();
+		let mut emulation = ImguiTouch::new(ImguiTouchConfig::default());
+
+		emulation.begin_frame(io);
+		emulation.handle_event(io, &touch(0, TouchPhase::Started, 10.0, 20.0), 1.0);
+		emulation.handle_event(io, &touch(0, TouchPhase::Ended, 10.0, 20.0), 1.0);
+		emulation.end_frame(io);
+		assert_eq!(io.mouse_pos, [10.0, 20.0]);
+		assert!(io.mouse_down[0]);
+
+		emulation.begin_frame(io);
+		emulation.end_frame(io);
+		assert!(!io.mouse_down[0]);
+	}
+
+	#[test]
+	fn drags_hold_the_button() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let io = test.state.io_mut();
+		let mut emulation = ImguiTouch::new(ImguiTouchConfig::default());
+
+		emulation.handle_event(io, &touch(0, TouchPhase::Started, 10.0, 10.0), 2.0);
+		emulation.handle_event(io, &touch(0, TouchPhase::Moved, 50.0, 10.0), 2.0);
+		assert_eq!(io.mouse_pos, [20.0, 20.0]);
+		assert!(io.mouse_down[0]);
+
+		emulation.begin_frame(io);
+		emulation.handle_event(io, &touch(0, TouchPhase::Moved, 60.0, 10.0), 2.0);
+		emulation.end_frame(io);
+		assert_eq!(io.mouse_pos, [120.0, 20.0]);
+		assert!(io.mouse_down[0]);
+
+		emulation.handle_event(io, &touch(0, TouchPhase::Ended, 60.0, 10.0), 2.0);
+		assert!(!io.mouse_down[0]);
+	}
+
+	#[test]
+	fn quick_drags_press_where_the_finger_went_down() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let io = test.state.io_mut();
+		let mut emulation = ImguiTouch::new(ImguiTouchConfig::default());
+
+		emulation.begin_frame(io);
+		emulation.handle_event(io, &touch(0, TouchPhase::Started, 10.0, 10.0), 2.0);
+		emulation.handle_event(io, &touch(0, TouchPhase::Moved, 50.0, 10.0), 2.0);
+		emulation.handle_event(io, &touch(0, TouchPhase::Moved, 70.0, 10.0), 2.0);
+		emulation.end_frame(io);
+		assert_eq!(io.mouse_pos, [20.0, 20.0]);
+		assert!(io.mouse_down[0]);
+
+		emulation.begin_frame(io);
+		assert_eq!(io.mouse_pos, [140.0, 20.0]);
+		assert!(io.mouse_down[0]);
+	}
+
+	#[test]
+	fn flicks_release_after_imgui_saw_the_press() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let io = test.state.io_mut();
+		let mut emulation = ImguiTouch::new(ImguiTouchConfig::default());
+
+		emulation.begin_frame(io);
+		emulation.handle_event(io, &touch(0, TouchPhase::Started, 10.0, 10.0), 1.0);
+		emulation.handle_event(io, &touch(0, TouchPhase::Moved, 50.0, 10.0), 1.0);
+		emulation.handle_event(io, &touch(0, TouchPhase::Ended, 50.0, 10.0), 1.0);
+		emulation.end_frame(io);
+		assert_eq!(io.mouse_pos, [10.0, 10.0]);
+		assert!(io.mouse_down[0]);
+
+		emulation.begin_frame(io);
+		assert!(!io.mouse_down[0]);
+	}
+
+	#[test]
+	fn two_fingers_scroll() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let io = test.state.io_mut();
+		let mut emulation = ImguiTouch::new(ImguiTouchConfig::default().with_scroll_step(10.0));
+
+		emulation.handle_event(io, &touch(0, TouchPhase::Started, 10.0, 10.0), 1.0);
+		emulation.handle_event(io, &touch(1, TouchPhase::Started, 30.0, 10.0), 1.0);
+		emulation.handle_event(io, &touch(1, TouchPhase::Moved, 30.0, 50.0), 1.0);
+		emulation.handle_event(io, &touch(0, TouchPhase::Moved, 50.0, 50.0), 1.0);
+		assert_eq!(io.mouse_wheel, 4.0);
+		assert_eq!(io.mouse_wheel_h, 2.0);
+
+		// Lifting one finger keeps scrolling with the other, from where it is.
+		emulation.handle_event(io, &touch(1, TouchPhase::Ended, 30.0, 50.0), 1.0);
+		emulation.handle_event(io, &touch(0, TouchPhase::Moved, 50.0, 30.0), 1.0);
+		assert_eq!(io.mouse_wheel, 2.0);
+		assert_eq!(io.mouse_wheel_h, 2.0);
+
+		emulation.handle_event(io, &touch(0, TouchPhase::Ended, 50.0, 30.0), 1.0);
+		emulation.end_frame(io);
+		assert!(!io.mouse_down[0]);
+		assert!(!io.mouse_down[1]);
+
+		emulation.begin_frame(io);
+		emulation.end_frame(io);
+		assert!(!io.mouse_down[0]);
+	}
+
+	#[test]
+	fn long_presses_right_click() {
+		let mut test = TestState::new(ImguiContextId::DEFAULT);
+		let io = test.state.io_mut();
+		let mut emulation = ImguiTouch::new(ImguiTouchConfig::default().with_long_press(Duration::from_millis(0)));
+
+		emulation.handle_event(io, &touch(0, TouchPhase::Started, 10.0, 10.0), 1.0);
+		emulation.end_frame(io);
+		assert!(io.mouse_down[1]);
+
+		// Lifting the finger afterwards does not click as well.
+		emulation.begin_frame(io);
+		emulation.handle_event(io, &touch(0, TouchPhase::Ended, 10.0, 10.0), 1.0);
+		emulation.end_frame(io);
+		assert!(!io.mouse_down[0]);
+		assert!(!io.mouse_down[1]);
+	}
+}